@@ -1,21 +1,70 @@
+use crate::shim::{CargoBin, SHIM_CARGO_ENV};
 use color_eyre::{
     eyre::{bail, Context},
     Result,
 };
-use std::{ffi::OsString, fmt, process::Command};
+use std::{
+    ffi::OsString,
+    fmt, io, mem,
+    path::PathBuf,
+    process::{Command, ExitStatus},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        OnceLock,
+    },
+};
+
+/// Set in the environment of the commands targo runs for a workspace (or a standalone build), to
+/// the workspace path (or the standalone build's key). Nested invocations of targo for the same
+/// workspace (e.g. from a build script running `cargo metadata`) see it and pass straight through
+/// to Cargo. Invocations for other workspaces, e.g. from a program started by `cargo run`, are
+/// handled as usual.
+pub(crate) const TARGO_ACTIVE_ENV: &str = "TARGO_ACTIVE";
+
+/// Returns the value of [`TARGO_ACTIVE_ENV`], if targo is running within a command that targo
+/// started.
+pub(crate) fn active_marker() -> Option<String> {
+    std::env::var(TARGO_ACTIVE_ENV)
+        .ok()
+        .filter(|marker| !marker.is_empty())
+}
+
+/// The Cargo binary that commands run, determined once per process.
+static CARGO_BIN: OnceLock<CargoBin> = OnceLock::new();
+
+/// Sets the Cargo binary to run. Has no effect once a command has been created.
+pub(crate) fn set_cargo_bin(cargo_bin: CargoBin) {
+    _ = CARGO_BIN.set(cargo_bin);
+}
 
 #[derive(Clone, Debug)]
 pub(crate) struct CargoCli {
-    cargo_bin: OsString,
+    cargo_bin: CargoBin,
     args: Vec<OsString>,
+    envs: Vec<(OsString, OsString)>,
+    current_dir: Option<PathBuf>,
 }
 
 impl CargoCli {
     pub(crate) fn new() -> Self {
-        let cargo_bin = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let cargo_bin = CARGO_BIN
+            .get()
+            .cloned()
+            .unwrap_or_else(|| CargoBin::plain("cargo".into()));
+        Self::with_bin(cargo_bin)
+    }
+
+    /// Creates a command for an arbitrary program, run the same way as Cargo.
+    pub(crate) fn program(program: impl Into<OsString>) -> Self {
+        Self::with_bin(CargoBin::plain(program.into()))
+    }
+
+    fn with_bin(cargo_bin: CargoBin) -> Self {
         Self {
             cargo_bin,
             args: Vec::new(),
+            envs: Vec::new(),
+            current_dir: None,
         }
     }
 
@@ -32,6 +81,16 @@ impl CargoCli {
         self
     }
 
+    pub(crate) fn env(&mut self, key: impl Into<OsString>, val: impl Into<OsString>) -> &mut Self {
+        self.envs.push((key.into(), val.into()));
+        self
+    }
+
+    pub(crate) fn current_dir(&mut self, dir: impl Into<PathBuf>) -> &mut Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
     #[cfg(test)]
     pub(crate) fn get_args(&self) -> &[OsString] {
         &self.args
@@ -67,17 +126,180 @@ impl CargoCli {
         Err(command.exec().into())
     }
 
+    /// Runs the command as a child process, waiting for it to exit.
+    ///
+    /// The child inherits targo's standard streams and process group, so its terminal setup is
+    /// identical to running Cargo directly. While waiting, signals sent to targo are forwarded to
+    /// the child (see [`ForwardSignals`]).
+    pub(crate) fn spawn_and_wait(&self) -> Result<ExitStatus> {
+        let mut command = self.make_command();
+        tracing::debug!("spawning command: {self}");
+        let mut child = command
+            .spawn()
+            .wrap_err_with(|| format!("failed to spawn `{self}`"))?;
+        // Handlers are installed after spawning so that the child starts out with the default
+        // dispositions, just as it would without targo.
+        let forward_signals = ForwardSignals::install(child.id());
+        let status = child
+            .wait()
+            .wrap_err_with(|| format!("failed to wait for `{self}`"));
+        drop(forward_signals);
+        status
+    }
+
     fn make_command(&self) -> Command {
-        let mut command = Command::new(&self.cargo_bin);
+        use std::os::unix::process::CommandExt;
+
+        let mut command = Command::new(&self.cargo_bin.path);
+        if let Some(arg0) = &self.cargo_bin.arg0 {
+            command.arg0(arg0);
+        }
+        if self.cargo_bin.from_shim {
+            command.env(SHIM_CARGO_ENV, &self.cargo_bin.path);
+        }
         command.args(&self.args);
+        command.envs(self.envs.iter().map(|(k, v)| (k, v)));
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
         command
     }
 }
 
 impl fmt::Display for CargoCli {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let iter = std::iter::once(self.cargo_bin.to_string_lossy())
+        let iter = std::iter::once(self.cargo_bin.path.to_string_lossy())
             .chain(self.args.iter().map(|arg| arg.to_string_lossy()));
         f.write_str(&shell_words::join(iter))
     }
 }
+
+/// The pid of the child that signals are forwarded to, or 0 if there isn't one.
+static FORWARD_TO_PID: AtomicI32 = AtomicI32::new(0);
+
+/// Signals that targo handles while waiting for Cargo.
+const FORWARDED_SIGNALS: [libc::c_int; 7] = [
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGHUP,
+    libc::SIGTSTP,
+    libc::SIGCONT,
+    libc::SIGWINCH,
+];
+
+/// Forwards signals to a child process until dropped, restoring the previous handlers.
+///
+/// Signals generated by the terminal (Ctrl-C, Ctrl-Z, window size changes) are delivered to the
+/// whole foreground process group, so the child already receives them directly. Signals sent to
+/// targo alone, e.g. `SIGTERM` from a CI runner, are forwarded to the child. In either case targo
+/// itself doesn't exit: it waits for the child and then exits with the child's status.
+struct ForwardSignals {
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+impl ForwardSignals {
+    fn install(child_pid: u32) -> Self {
+        FORWARD_TO_PID.store(child_pid as i32, Ordering::SeqCst);
+
+        let mut previous = Vec::with_capacity(FORWARDED_SIGNALS.len());
+        for signal in FORWARDED_SIGNALS {
+            // SAFETY: sigaction is zero-initializable, and the handler only calls
+            // async-signal-safe functions.
+            unsafe {
+                let mut action: libc::sigaction = mem::zeroed();
+                action.sa_sigaction = forward_signal as *const () as libc::sighandler_t;
+                action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut old: libc::sigaction = mem::zeroed();
+                if libc::sigaction(signal, &action, &mut old) == 0 {
+                    previous.push((signal, old));
+                } else {
+                    tracing::debug!(
+                        "failed to install handler for signal {signal}: {}",
+                        io::Error::last_os_error()
+                    );
+                }
+            }
+        }
+
+        Self { previous }
+    }
+}
+
+impl Drop for ForwardSignals {
+    fn drop(&mut self) {
+        for (signal, old) in &self.previous {
+            // SAFETY: restores a disposition previously returned by sigaction.
+            unsafe {
+                libc::sigaction(*signal, old, std::ptr::null_mut());
+            }
+        }
+        FORWARD_TO_PID.store(0, Ordering::SeqCst);
+    }
+}
+
+extern "C" fn forward_signal(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _context: *mut libc::c_void,
+) {
+    let pid = FORWARD_TO_PID.load(Ordering::SeqCst);
+    // SAFETY: with SA_SIGINFO, info points to a valid siginfo_t.
+    if pid > 0 && is_sent_by_process(unsafe { &*info }) {
+        // SAFETY: kill is async-signal-safe.
+        unsafe {
+            libc::kill(pid, signal);
+        }
+    }
+    if signal == libc::SIGTSTP {
+        // Installing a handler replaces the default action of stopping the process, so stop
+        // explicitly. The child is stopped too, either by the terminal or by the forwarded
+        // signal, and both are resumed together by SIGCONT.
+        // SAFETY: raise is async-signal-safe.
+        unsafe {
+            libc::raise(libc::SIGSTOP);
+        }
+    }
+}
+
+/// Whether a signal was sent by a process (with `kill` or similar), as opposed to being
+/// generated by the kernel or the terminal.
+fn is_sent_by_process(info: &libc::siginfo_t) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        // SI_USER, SI_QUEUE, SI_TKILL and friends are all non-positive.
+        info.si_code <= 0
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        info.si_code == libc::SI_USER
+    }
+}
+
+/// Exits the current process with the same status as a child process.
+///
+/// If the child was terminated by a signal, the signal is re-raised so that the parent of targo
+/// observes the same termination. Since this doesn't return, any locks or other resources must
+/// be released before calling it.
+pub(crate) fn exit_with_status(status: ExitStatus) -> ! {
+    use std::os::unix::process::ExitStatusExt;
+
+    if let Some(code) = status.code() {
+        std::process::exit(code);
+    }
+    if let Some(signal) = status.signal() {
+        // SAFETY: resetting a signal to its default disposition and raising it are both safe
+        // to do at any point.
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+        // The signal didn't terminate the process (e.g. it's a stop signal), so fall back to the
+        // shell convention for signal exits.
+        std::process::exit(128 + signal);
+    }
+
+    // Not reachable for a process that has exited, but exit with a failure just in case.
+    std::process::exit(1);
+}
@@ -1 +1,561 @@
-// TODO
+use crate::metadata::TargetDirUse;
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::TimeDelta;
+use color_eyre::{
+    eyre::{bail, Context},
+    Result,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// User configuration for targo, read from `config.toml` in the store directory.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct TargoConfig {
+    /// How managed target directories are made available to workspaces by default.
+    #[serde(default)]
+    pub(crate) placement: Placement,
+    /// How Cargo is run once the target directory is set up.
+    #[serde(default)]
+    pub(crate) run_mode: RunMode,
+    #[serde(default)]
+    pub(crate) install: InstallConfig,
+    #[serde(default)]
+    pub(crate) gc: GcConfig,
+    #[serde(default)]
+    pub(crate) shared_deps: SharedDepsConfig,
+    /// Additional storage roots, beyond the default one in the store directory.
+    #[serde(default, rename = "root")]
+    pub(crate) roots: Vec<RootConfig>,
+    /// Rules for picking a root for new target directories. The first matching rule wins.
+    #[serde(default, rename = "root-rule")]
+    pub(crate) root_rules: Vec<RootRule>,
+    /// Per-workspace overrides.
+    #[serde(default, rename = "workspace")]
+    pub(crate) workspaces: Vec<WorkspaceConfig>,
+}
+
+impl TargoConfig {
+    pub(crate) const CONFIG_FILE_NAME: &'static str = "config.toml";
+
+    /// Loads the config from the store directory, returning the default config if it doesn't
+    /// exist.
+    pub(crate) fn load(store_dir: &Utf8Path) -> Result<Self> {
+        let path = store_dir.join(Self::CONFIG_FILE_NAME);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err)
+                    .wrap_err_with(|| format!("failed to read targo config at `{path}`"))
+            }
+        };
+        Self::from_toml(&contents)
+            .wrap_err_with(|| format!("failed to parse targo config at `{path}`"))
+    }
+
+    fn from_toml(contents: &str) -> Result<Self> {
+        let config: Self = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut names = vec![RootConfig::DEFAULT_NAME];
+        for root in &self.roots {
+            if names.contains(&root.name.as_str()) {
+                bail!("root name `{}` is used more than once", root.name);
+            }
+            names.push(&root.name);
+        }
+
+        let referenced = self.root_rules.iter().map(|rule| &rule.root).chain(
+            self.workspaces
+                .iter()
+                .filter_map(|config| config.root.as_ref()),
+        );
+        for name in referenced {
+            if !names.contains(&name.as_str()) {
+                bail!("unknown root `{name}` (known roots: {})", names.join(", "));
+            }
+        }
+
+        for root in &self.roots {
+            if root.min_free_percent > 100 {
+                bail!("root `{}`: min-free-percent must be at most 100", root.name);
+            }
+            let Some(persist_to) = &root.persist_to else {
+                continue;
+            };
+            if !root.volatile {
+                bail!(
+                    "root `{}`: persist-to is only valid for volatile roots",
+                    root.name
+                );
+            }
+            let is_persistent = persist_to == RootConfig::DEFAULT_NAME
+                || self
+                    .roots
+                    .iter()
+                    .any(|other| &other.name == persist_to && !other.volatile);
+            if !is_persistent {
+                bail!(
+                    "root `{}`: persist-to must refer to a known non-volatile root, \
+                     found `{persist_to}`",
+                    root.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the name of the root that a new target directory should be placed in.
+    ///
+    /// `path` is the workspace or script that the target directory is for, and `size` returns
+    /// the size of the workspace's existing target directory, if any. That directory is discarded
+    /// by `wrap-cargo` and `targo adopt`, and moved into the store by `targo migrate-tree`.
+    ///
+    /// This is only called when a managed directory is first created, so size rules only apply
+    /// on first adoption or migration. Workspaces without a `target` directory count as empty,
+    /// and later builds keep using the root chosen then, however large they get.
+    pub(crate) fn root_for(&self, path: Option<&Utf8Path>, size: impl FnOnce() -> u64) -> &str {
+        if let Some(path) = path {
+            if let Some(root) = self.workspace_configs(path).find_map(|c| c.root.as_deref()) {
+                return root;
+            }
+        }
+
+        let mut size = LazySize::new(size);
+        for rule in &self.root_rules {
+            if let Some(prefix) = &rule.path_prefix {
+                if !path.is_some_and(|path| path.starts_with(prefix)) {
+                    continue;
+                }
+            }
+            if let Some(min_size_gib) = rule.min_size_gib {
+                if size.get() < min_size_gib.saturating_mul(1024 * 1024 * 1024) {
+                    continue;
+                }
+            }
+            return &rule.root;
+        }
+
+        RootConfig::DEFAULT_NAME
+    }
+
+    /// Returns the placement strategy for the given workspace.
+    pub(crate) fn placement_for(&self, workspace_dir: &Utf8Path) -> Placement {
+        self.workspace_configs(workspace_dir)
+            .find_map(|config| config.placement)
+            .unwrap_or(self.placement)
+    }
+
+    /// Returns true if targo should leave this workspace alone.
+    pub(crate) fn is_excluded(&self, workspace_dir: &Utf8Path) -> bool {
+        self.workspace_configs(workspace_dir)
+            .find_map(|config| config.exclude)
+            .unwrap_or(false)
+    }
+
+    /// Returns the per-workspace overrides that apply to this workspace, most specific first.
+    fn workspace_configs(&self, workspace_dir: &Utf8Path) -> std::vec::IntoIter<&WorkspaceConfig> {
+        let mut configs: Vec<_> = self
+            .workspaces
+            .iter()
+            .filter(|config| workspace_dir.starts_with(&config.path))
+            .collect();
+        configs.sort_by_key(|config| std::cmp::Reverse(config.path.components().count()));
+        configs.into_iter()
+    }
+}
+
+/// How a managed target directory is made available to a workspace.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Placement {
+    /// Replace the workspace's `target` directory with a symlink into the store.
+    #[default]
+    Symlink,
+    /// Leave the workspace untouched, and point Cargo at the store by setting
+    /// `CARGO_TARGET_DIR`.
+    ///
+    /// This is useful for read-only checkouts, bind mounts and sandboxes that can't tolerate a
+    /// symlink.
+    Env,
+}
+
+/// How targo runs Cargo.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RunMode {
+    /// Replace the targo process with Cargo.
+    #[default]
+    Exec,
+    /// Run Cargo as a child process and wait for it to exit, so that targo can do work after
+    /// the build finishes. Cargo's exit status is propagated exactly.
+    Spawn,
+}
+
+/// Overrides for workspaces within a directory.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct WorkspaceConfig {
+    /// The workspace, or a directory containing workspaces, that this applies to.
+    pub(crate) path: Utf8PathBuf,
+    #[serde(default)]
+    pub(crate) placement: Option<Placement>,
+    /// Pins target directories for these workspaces to this root.
+    #[serde(default)]
+    pub(crate) root: Option<String>,
+    /// If true, targo leaves these workspaces alone. A more specific override can set this to
+    /// false to re-include workspaces.
+    #[serde(default)]
+    pub(crate) exclude: Option<bool>,
+}
+
+/// A storage root that managed target directories can be placed in.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct RootConfig {
+    pub(crate) name: String,
+    pub(crate) path: Utf8PathBuf,
+    /// Whether this root is on a volatile filesystem like tmpfs, whose contents can vanish
+    /// (e.g. on reboot).
+    #[serde(default)]
+    pub(crate) volatile: bool,
+    /// For volatile roots, the persistent root that `targo store sync` saves snapshots to.
+    ///
+    /// Snapshots are restored when a target directory in this root has vanished.
+    #[serde(default)]
+    pub(crate) persist_to: Option<String>,
+    /// For volatile roots, the least recently used target directories are evicted when less
+    /// than this percentage of the filesystem is free.
+    #[serde(default = "RootConfig::default_min_free_percent")]
+    pub(crate) min_free_percent: u8,
+}
+
+impl RootConfig {
+    /// The name of the root in the store directory, which is always present.
+    pub(crate) const DEFAULT_NAME: &'static str = "default";
+
+    fn default_min_free_percent() -> u8 {
+        25
+    }
+}
+
+/// A rule for placing new target directories in a root. All specified conditions must match.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct RootRule {
+    /// The root to use if this rule matches.
+    pub(crate) root: String,
+    /// Match workspaces and scripts within this directory.
+    #[serde(default)]
+    pub(crate) path_prefix: Option<Utf8PathBuf>,
+    /// Match when the workspace's existing target directory is at least this large, whether
+    /// it's about to be discarded or moved into the store. Only checked when the managed
+    /// directory is first created.
+    #[serde(default)]
+    pub(crate) min_size_gib: Option<u64>,
+}
+
+/// A size that's computed at most once, and only if required.
+struct LazySize<F> {
+    f: Option<F>,
+    size: u64,
+}
+
+impl<F: FnOnce() -> u64> LazySize<F> {
+    fn new(f: F) -> Self {
+        Self {
+            f: Some(f),
+            size: 0,
+        }
+    }
+
+    fn get(&mut self) -> u64 {
+        if let Some(f) = self.f.take() {
+            self.size = f();
+        }
+        self.size
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct InstallConfig {
+    /// Whether to build `cargo install` in a persistent target directory in the store, so that
+    /// reinstalls and upgrades can reuse earlier builds.
+    #[serde(default)]
+    pub(crate) cache: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct SharedDepsConfig {
+    /// Whether to store identical registry dependency artifacts once per root, hard-linked into
+    /// each managed target directory that uses them.
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    /// Whether to share a managed directory's artifacts after each build. Only applies in spawn
+    /// mode.
+    #[serde(default)]
+    pub(crate) after_build: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct GcConfig {
+    /// The number of days after which an unused target directory is garbage collected.
+    #[serde(default = "GcConfig::default_max_age_days")]
+    pub(crate) max_age_days: u32,
+    /// The number of days after which an unused Cargo script's target directory is garbage
+    /// collected.
+    ///
+    /// Scripts are often one-offs, so this is shorter than `max-age-days` by default.
+    #[serde(default = "GcConfig::default_script_max_age_days")]
+    pub(crate) script_max_age_days: u32,
+}
+
+impl GcConfig {
+    fn default_max_age_days() -> u32 {
+        30
+    }
+
+    fn default_script_max_age_days() -> u32 {
+        7
+    }
+
+    /// Returns how long a target directory with the given use can go unused before it's
+    /// garbage collected.
+    pub(crate) fn max_age(&self, used_for: Option<&TargetDirUse>) -> TimeDelta {
+        let days = match used_for {
+            Some(TargetDirUse::Script { .. }) => self.script_max_age_days,
+            Some(TargetDirUse::Install(_)) | None => self.max_age_days,
+        };
+        TimeDelta::days(days.into())
+    }
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            max_age_days: Self::default_max_age_days(),
+            script_max_age_days: Self::default_script_max_age_days(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() -> Result<()> {
+        let config = TargoConfig::from_toml("")?;
+        assert_eq!(config.run_mode, RunMode::Exec, "exec by default");
+        assert!(!config.install.cache, "install cache is off by default");
+        assert_eq!(config.gc.max_age_days, 30, "default max age");
+        assert_eq!(config.gc.script_max_age_days, 7, "default script max age");
+
+        let config = TargoConfig::from_toml("[gc]\nscript-max-age-days = 1\n")?;
+        assert_eq!(config.gc.max_age_days, 30, "max age not overridden");
+        let script = TargetDirUse::Script {
+            path: "/tmp/foo.rs".into(),
+        };
+        assert_eq!(config.gc.max_age(Some(&script)), TimeDelta::days(1));
+        assert_eq!(config.gc.max_age(None), TimeDelta::days(30));
+
+        let config = TargoConfig::from_toml("run-mode = \"spawn\"\n[install]\ncache = true\n")?;
+        assert_eq!(config.run_mode, RunMode::Spawn, "spawn mode enabled");
+        assert!(config.install.cache, "install cache enabled");
+
+        assert!(
+            TargoConfig::from_toml("[install]\nunknown = true\n").is_err(),
+            "unknown keys are rejected"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_excluded() -> Result<()> {
+        let config = TargoConfig::from_toml(
+            r#"
+            [[workspace]]
+            path = "/home/me/vendor"
+            exclude = true
+
+            [[workspace]]
+            path = "/home/me/vendor/mine"
+            exclude = false
+
+            [[workspace]]
+            path = "/home/me/vendor/mine/nested"
+            placement = "env"
+            "#,
+        )?;
+        let data = [
+            ("/home/me/dev/foo", false),
+            ("/home/me/vendor", true),
+            ("/home/me/vendor/foo", true),
+            ("/home/me/vendor/mine", false),
+            // Overrides without exclude inherit it from less specific ones.
+            ("/home/me/vendor/mine/nested", false),
+        ];
+        for (workspace_dir, expected) in data {
+            assert_eq!(
+                config.is_excluded(workspace_dir.into()),
+                expected,
+                "excluded matches for {workspace_dir}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_placement_for() -> Result<()> {
+        let config = TargoConfig::from_toml("")?;
+        assert_eq!(
+            config.placement_for("/home/me/dev/foo".into()),
+            Placement::Symlink
+        );
+
+        let config = TargoConfig::from_toml(
+            r#"
+            placement = "env"
+
+            [[workspace]]
+            path = "/home/me/dev/rw"
+            placement = "symlink"
+
+            [[workspace]]
+            path = "/home/me/dev/rw/nested"
+            "#,
+        )?;
+        let data = [
+            ("/home/me/dev/foo", Placement::Env),
+            ("/home/me/dev/rw", Placement::Symlink),
+            // Overrides without a placement inherit it from less specific ones.
+            ("/home/me/dev/rw/nested", Placement::Symlink),
+            // Path prefixes are matched by component.
+            ("/home/me/dev/rwx", Placement::Env),
+        ];
+        for (workspace_dir, expected) in data {
+            assert_eq!(
+                config.placement_for(workspace_dir.into()),
+                expected,
+                "placement matches for {workspace_dir}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_root_for() -> Result<()> {
+        let config = TargoConfig::from_toml(
+            r#"
+            [[root]]
+            name = "nvme"
+            path = "/mnt/nvme/targo"
+
+            [[root]]
+            name = "hdd"
+            path = "/mnt/hdd/targo"
+
+            [[root-rule]]
+            path-prefix = "/home/me/big"
+            root = "hdd"
+
+            [[root-rule]]
+            min-size-gib = 20
+            root = "hdd"
+
+            [[root-rule]]
+            path-prefix = "/home/me/dev"
+            root = "nvme"
+
+            [[workspace]]
+            path = "/home/me/big/pinned"
+            root = "nvme"
+            "#,
+        )?;
+        const GIB: u64 = 1024 * 1024 * 1024;
+        let data = [
+            (Some("/home/me/other"), 0, "default"),
+            (None, 0, "default"),
+            (None, 20 * GIB, "hdd"),
+            (Some("/home/me/big/foo"), 0, "hdd"),
+            (Some("/home/me/big/pinned"), 0, "nvme"),
+            (Some("/home/me/dev/foo"), 0, "nvme"),
+            (Some("/home/me/dev/foo"), 20 * GIB, "hdd"),
+        ];
+        for (path, size, expected) in data {
+            assert_eq!(
+                config.root_for(path.map(Utf8Path::new), || size),
+                expected,
+                "root matches for {path:?} with size {size}"
+            );
+        }
+
+        // Rules must refer to known roots.
+        let err =
+            TargoConfig::from_toml("[[root-rule]]\nroot = \"nvme\"\n").expect_err("unknown root");
+        assert!(err.to_string().contains("unknown root `nvme`"), "{err}");
+        let err = TargoConfig::from_toml("[[root]]\nname = \"default\"\npath = \"/foo\"\n")
+            .expect_err("duplicate root");
+        assert!(err.to_string().contains("used more than once"), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_volatile_root() -> Result<()> {
+        let config = TargoConfig::from_toml(
+            r#"
+            [[root]]
+            name = "shm"
+            path = "/dev/shm/targo"
+            volatile = true
+            persist-to = "default"
+            "#,
+        )?;
+        assert!(config.roots[0].volatile, "root is volatile");
+        assert_eq!(
+            config.roots[0].min_free_percent, 25,
+            "default min free percent"
+        );
+
+        let err = TargoConfig::from_toml(
+            r#"
+            [[root]]
+            name = "shm"
+            path = "/dev/shm/targo"
+            volatile = true
+            persist-to = "shm"
+            "#,
+        )
+        .expect_err("volatile roots can't persist to themselves");
+        assert!(err.to_string().contains("non-volatile root"), "{err}");
+
+        let err = TargoConfig::from_toml(
+            r#"
+            [[root]]
+            name = "hdd"
+            path = "/mnt/hdd/targo"
+            persist-to = "default"
+            "#,
+        )
+        .expect_err("persist-to requires a volatile root");
+        assert!(
+            err.to_string().contains("only valid for volatile roots"),
+            "{err}"
+        );
+
+        Ok(())
+    }
+}
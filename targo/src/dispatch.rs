@@ -1,14 +1,32 @@
-use crate::{cargo_cli::CargoCli, store::TargoStore};
-use camino::Utf8PathBuf;
-use clap::{Parser, Subcommand, ValueHint};
+use crate::{
+    cargo_cli::{active_marker, exit_with_status, set_cargo_bin, CargoCli, TARGO_ACTIVE_ENV},
+    config::{Placement, RunMode, TargoConfig},
+    helpers::{dir_size, format_bytes, is_writable},
+    metadata::{HistoryRecord, InstallSource, TargetDirUse},
+    migrate::find_candidates,
+    output::{
+        json_schemas, print_json, AdoptReport, AdoptedWorkspace, HistoryReport, ManagedDirStatus,
+        MigrateTreeReport, MigratedWorkspace, SkipReason, SkippedWorkspace, StatusAction,
+        StatusReport, TargetDirKindName,
+    },
+    shell::init_script,
+    shim::{invoked_as_cargo, strip_cargo_subcommand, CargoBin},
+    store::{target_dir_key, ManagedTargetDir, TargetDirState, TargoStore},
+};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use chrono::Local;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum, ValueHint};
 use color_eyre::{
-    eyre::{bail, WrapErr},
+    eyre::{bail, eyre, WrapErr},
     Result,
 };
 use lexopt::prelude::*;
 use std::{
     ffi::{OsStr, OsString},
+    io,
     path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    time::Instant,
 };
 use tracing_subscriber::EnvFilter;
 
@@ -33,43 +51,1206 @@ pub enum TargoCommand {
         )]
         args: Vec<OsString>,
     },
+
+    /// Manage the store that target directories are kept in.
+    Store {
+        #[command(subcommand)]
+        command: StoreCommand,
+    },
+
+    /// Remove managed target directories that haven't been used recently.
+    Gc {
+        /// Print what would be removed without removing anything.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Override the number of days after which unused target directories are removed.
+        #[arg(long, value_name = "DAYS")]
+        max_age_days: Option<u32>,
+
+        /// The output format.
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Show how targo would handle a workspace, without changing anything.
+    Status {
+        /// The workspace to show the status of [default: current directory].
+        #[arg(value_hint = ValueHint::DirPath)]
+        workspace: Option<PathBuf>,
+
+        /// The output format.
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Run a command with the workspace's managed target directory.
+    ///
+    /// The managed directory is set up as for `wrap-cargo`, so an existing `target` directory is
+    /// replaced with an empty managed one. `CARGO_TARGET_DIR` and `TARGO_WORKSPACE` are set for
+    /// the command. This is useful for tools that read `CARGO_TARGET_DIR` or expect `target` to
+    /// exist before Cargo runs.
+    Exec {
+        /// The command and its arguments.
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_hint = ValueHint::CommandWithArguments,
+        )]
+        command: Vec<OsString>,
+    },
+
+    /// Print the location of a workspace's managed target directory.
+    ///
+    /// Exits with code 3 if the directory isn't within a Cargo workspace, 4 if the workspace is
+    /// excluded by config, and 5 if the workspace doesn't have a managed target directory.
+    TargetDir {
+        /// The workspace to print the target directory for [default: current directory].
+        #[arg(value_hint = ValueHint::DirPath)]
+        workspace: Option<PathBuf>,
+
+        /// What to print.
+        #[arg(long, value_enum, default_value_t)]
+        print: TargetDirPrint,
+    },
+
+    /// Print a managed target directory for rust-analyzer, separate from the one used by builds.
+    ///
+    /// Point `rust-analyzer.cargo.targetDir` at the printed path, so that IDE checks don't wait
+    /// on the build directory lock held by terminal builds. The directory is stored next to the
+    /// workspace's managed `target` directory, and is garbage collected along with it. The
+    /// managed directory must already exist, e.g. from a build run with targo.
+    RaTargetDir {
+        /// The workspace to print the directory for [default: current directory].
+        #[arg(value_hint = ValueHint::DirPath)]
+        workspace: Option<PathBuf>,
+    },
+
+    /// Start managing workspaces' target directories without running Cargo.
+    ///
+    /// This sets up target directories the same way `wrap-cargo` does, so existing target
+    /// directories are replaced with empty managed ones. Workspaces that were ejected are managed
+    /// again.
+    Adopt {
+        /// The workspaces to adopt [default: current directory].
+        #[arg(value_hint = ValueHint::DirPath)]
+        paths: Vec<PathBuf>,
+
+        /// Print what would be done without changing anything.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// The name of the target directory, relative to the workspace root.
+        ///
+        /// Other commands, including `wrap-cargo`, `status`, `eject` and `target-dir`, only know
+        /// about `target`, so a directory adopted under another name is only used by tools that
+        /// are pointed at it explicitly.
+        #[arg(long, value_name = "NAME", default_value = "target")]
+        target_dir_name: Utf8PathBuf,
+
+        /// The output format.
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Turn a workspace's managed target directory back into a real directory.
+    ///
+    /// The workspace is recorded as opted out, so that targo leaves it alone from now on.
+    Eject {
+        /// The workspace to eject [default: current directory].
+        #[arg(value_hint = ValueHint::DirPath)]
+        workspace: Option<PathBuf>,
+
+        /// The output format.
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Move the `target` directories of all workspaces under a directory into the store.
+    ///
+    /// Excluded and ejected workspaces, and those whose `target` isn't a plain directory, are
+    /// skipped.
+    MigrateTree {
+        /// The directory to scan for workspaces.
+        #[arg(value_hint = ValueHint::DirPath)]
+        root: PathBuf,
+
+        /// Print what would be moved without changing anything.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// The output format.
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Store identical registry dependency artifacts once, shared between managed directories.
+    ///
+    /// Artifacts of registry crates in `deps/` that Cargo has a fingerprint for are identified by
+    /// their metadata hash and contents, stored once per store root, and hard-linked into each
+    /// managed target directory. Requires `shared-deps.enabled = true` in the config. `targo gc`
+    /// removes shared artifacts that are no longer used.
+    ShareDeps {
+        /// Print what would be shared without changing anything.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// The output format.
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Show a summary of disk usage across the store.
+    Stats {
+        /// List directories unused for at least this many days.
+        #[arg(long, value_name = "DAYS", default_value_t = 30)]
+        unused_days: u32,
+
+        /// The number of directories to list as largest and most recently used.
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        top: usize,
+
+        /// The output format.
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Show the build history of a workspace's managed target directory.
+    ///
+    /// History is only recorded when `run-mode = "spawn"` is configured. In the default exec
+    /// mode, targo is replaced by Cargo, so nothing is recorded.
+    History {
+        /// The workspace to show history for [default: current directory].
+        #[arg(value_hint = ValueHint::DirPath)]
+        workspace: Option<PathBuf>,
+
+        /// Only show the most recent N builds.
+        #[arg(long, short = 'n', value_name = "N")]
+        limit: Option<usize>,
+
+        /// The output format.
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Print shell integration that runs Cargo through targo.
+    ///
+    /// For example, add `eval "$(targo init zsh)"` to your `.zshrc`, or
+    /// `targo init fish | source` to your fish config.
+    Init {
+        /// The shell to print integration for.
+        #[arg(value_enum)]
+        shell: InitShell,
+    },
+
+    /// Print a completion script for targo.
+    Completions {
+        /// The shell to print completions for.
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Print the JSON schema for the `--message-format json` output of a command.
+    ///
+    /// The schemas are also checked into the `schemas/` directory of the targo repository.
+    JsonSchema {
+        /// The command to print the schema for, e.g. `gc` or `store-move-dir`.
+        command: String,
+    },
+}
+
+/// Shells supported by `targo init`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum InitShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// What `targo target-dir` prints.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TargetDirPrint {
+    /// The managed `target` directory in the store, with symlinks resolved.
+    #[default]
+    Managed,
+    /// The symlink in the workspace that points to the managed directory.
+    Symlink,
+    /// The name of the managed directory within its store root.
+    Encoded,
+    /// The store root containing the managed directory.
+    Root,
+}
+
+/// Output formats for management commands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum MessageFormat {
+    /// Human-readable output.
+    #[default]
+    Human,
+    /// Machine-readable JSON output on stdout, following the schemas in `schemas/`.
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum StoreCommand {
+    /// Move a workspace's managed target directory to another root.
+    MoveDir {
+        /// The workspace whose target directory should be moved.
+        #[arg(value_hint = ValueHint::DirPath)]
+        workspace: PathBuf,
+
+        /// The name of the root to move the directory to.
+        root: String,
+
+        /// The output format.
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Save a snapshot of a workspace's target directory in a volatile root to its persistent
+    /// root.
+    ///
+    /// The snapshot is restored if the target directory vanishes, e.g. after a reboot.
+    Sync {
+        /// The workspace whose target directory should be saved.
+        #[arg(value_hint = ValueHint::DirPath)]
+        workspace: PathBuf,
+
+        /// The output format.
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
 }
 
 impl TargoApp {
+    /// Parses the command line, whether targo was run directly or as `cargo targo`.
+    pub fn parse_env() -> Self {
+        let mut args: Vec<OsString> = std::env::args_os().collect();
+        let mut command = Self::command();
+        if strip_cargo_subcommand(&mut args) {
+            command = command.bin_name("cargo targo");
+        }
+        let matches = command.get_matches_from(args);
+        Self::from_arg_matches(&matches).unwrap_or_else(|err| err.exit())
+    }
+
     pub fn exec(self) -> Result<()> {
         let filter = EnvFilter::from_env("TARGO_LOG");
         tracing_subscriber::fmt().with_env_filter(filter).init();
+        set_cargo_bin(CargoBin::from_env()?);
         match self.command {
             TargoCommand::WrapCargo { args } => exec_wrap_cargo(args),
+            TargoCommand::Exec { command } => exec_exec(command),
+            TargoCommand::RaTargetDir { workspace } => exec_ra_target_dir(workspace.as_deref()),
+            TargoCommand::TargetDir { workspace, print } => {
+                exec_target_dir(workspace.as_deref(), print)
+            }
+            TargoCommand::Store {
+                command:
+                    StoreCommand::MoveDir {
+                        workspace,
+                        root,
+                        message_format,
+                    },
+            } => exec_store_move_dir(&workspace, &root, message_format),
+            TargoCommand::Store {
+                command:
+                    StoreCommand::Sync {
+                        workspace,
+                        message_format,
+                    },
+            } => exec_store_sync(&workspace, message_format),
+            TargoCommand::Gc {
+                dry_run,
+                max_age_days,
+                message_format,
+            } => exec_gc(dry_run, max_age_days, message_format),
+            TargoCommand::Status {
+                workspace,
+                message_format,
+            } => exec_status(workspace.as_deref(), message_format),
+            TargoCommand::Adopt {
+                paths,
+                dry_run,
+                target_dir_name,
+                message_format,
+            } => exec_adopt(paths, dry_run, &target_dir_name, message_format),
+            TargoCommand::Eject {
+                workspace,
+                message_format,
+            } => exec_eject(workspace.as_deref(), message_format),
+            TargoCommand::MigrateTree {
+                root,
+                dry_run,
+                message_format,
+            } => exec_migrate_tree(&root, dry_run, message_format),
+            TargoCommand::ShareDeps {
+                dry_run,
+                message_format,
+            } => exec_share_deps(dry_run, message_format),
+            TargoCommand::Stats {
+                unused_days,
+                top,
+                message_format,
+            } => exec_stats(unused_days, top, message_format),
+            TargoCommand::History {
+                workspace,
+                limit,
+                message_format,
+            } => exec_history(workspace.as_deref(), limit, message_format),
+            TargoCommand::Init { shell } => {
+                print!("{}", init_script(shell));
+                Ok(())
+            }
+            TargoCommand::Completions { shell } => {
+                clap_complete::generate(shell, &mut Self::command(), "targo", &mut io::stdout());
+                Ok(())
+            }
+            TargoCommand::JsonSchema { command } => exec_json_schema(&command),
         }
     }
 }
 
+/// Returns true if targo was invoked through a binary or link named `cargo`.
+pub fn is_cargo_shim(argv0: &OsStr) -> bool {
+    invoked_as_cargo(argv0)
+}
+
+/// Runs targo as a drop-in replacement for `cargo`, like `targo wrap-cargo` but with the real
+/// Cargo found by searching `PATH`.
+pub fn exec_cargo_shim(args: Vec<OsString>) -> Result<()> {
+    let filter = EnvFilter::from_env("TARGO_LOG");
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+    set_cargo_bin(CargoBin::for_shim()?);
+    exec_wrap_cargo(args)
+}
+
 fn exec_wrap_cargo(args: Vec<OsString>) -> Result<()> {
-    let parser = lexopt::Parser::from_args(args);
-    let parsed_args = match WrapCargoArgs::new(parser)? {
+    if let Some(marker) = active_marker() {
+        let parsed_args =
+            ParsedCargoArgs::new(args.clone()).with_context(|| "error parsing Cargo arguments")?;
+        if parsed_args.is_nested_in(&marker) {
+            // The outer targo has already set up the target directory, and its Cargo may hold
+            // the build lock, so don't touch the store.
+            tracing::debug!("running within targo for {marker}, passing through to cargo");
+            return parsed_args.cargo_command().run_or_exec();
+        }
+    }
+
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+
+    let wrap_args = WrapCargoArgs::new(args, &config)?;
+    let active_marker = wrap_args.active_marker();
+    let (parsed_args, mut command, managed_dir) = match wrap_args {
         WrapCargoArgs::Enabled {
             parsed_args,
             workspace_dir,
             target_dir,
         } => {
             // Find the target directory destination.
-            let store_dir = find_targo_store_dir()?;
-            let store = TargoStore::new(store_dir)?;
+            let store = TargoStore::new(store_dir.clone(), &config)?;
+
+            match config.placement_for(&workspace_dir) {
+                _ if store.is_opted_out(&workspace_dir) => {
+                    tracing::debug!("workspace {workspace_dir} was ejected, disabling");
+                    let command = parsed_args.cargo_command();
+                    (parsed_args, command, None)
+                }
+                Placement::Symlink => {
+                    let kind = store.determine_target_dir(&workspace_dir, &target_dir)?;
+                    let managed_dir = store.actualize_kind(kind)?;
 
-            let kind = store.determine_target_dir(&workspace_dir, &target_dir)?;
-            store.actualize_kind(kind)?;
+                    let mut command = parsed_args.cargo_command();
+                    if let Some(managed_dir) = &managed_dir {
+                        if managed_dir.placement() == Placement::Env
+                            && !parsed_args.has_explicit_target_dir()
+                        {
+                            eprintln!(
+                                "[targo] workspace `{workspace_dir}` is not writable, \
+                                 setting CARGO_TARGET_DIR instead"
+                            );
+                            command.env("CARGO_TARGET_DIR", managed_dir.target_dir());
+                        }
+                    }
+                    (parsed_args, command, managed_dir)
+                }
+                Placement::Env if parsed_args.has_explicit_target_dir() => {
+                    let command = parsed_args.cargo_command();
+                    (parsed_args, command, None)
+                }
+                Placement::Env => {
+                    let managed_dir = store.env_target_dir(&workspace_dir)?;
+
+                    let mut command = parsed_args.cargo_command();
+                    command.env("CARGO_TARGET_DIR", managed_dir.target_dir());
+                    (parsed_args, command, Some(managed_dir))
+                }
+            }
+        }
+        WrapCargoArgs::Standalone {
+            parsed_args,
+            used_for,
+        } => {
+            let store = TargoStore::new(store_dir.clone(), &config)?;
+            let managed_dir = store.standalone_target_dir(&used_for)?;
 
-            parsed_args
+            let mut command = parsed_args.cargo_command();
+            command.env("CARGO_TARGET_DIR", managed_dir.target_dir());
+            (parsed_args, command, Some(managed_dir))
+        }
+        WrapCargoArgs::Disabled { parsed_args } => {
+            let command = parsed_args.cargo_command();
+            (parsed_args, command, None)
         }
-        WrapCargoArgs::Disabled { parsed_args } => parsed_args,
     };
+    if let Some(marker) = &active_marker {
+        command.env(TARGO_ACTIVE_ENV, marker);
+    }
 
-    parsed_args.cargo_command().run_or_exec()?;
+    match config.run_mode {
+        RunMode::Exec => command.run_or_exec()?,
+        RunMode::Spawn => {
+            // Keep the target directory marked as in use until Cargo exits, however it exits,
+            // so that it isn't garbage collected or evicted halfway through a build.
+            let in_use = managed_dir
+                .as_ref()
+                .map(|managed_dir| managed_dir.lock_in_use())
+                .transpose()?;
+            let history = managed_dir
+                .as_ref()
+                .map(|managed_dir| HistoryStart::new(managed_dir, &parsed_args));
+            let status = command.spawn_and_wait();
+            if let (Some(managed_dir), Some(history), Ok(status)) = (&managed_dir, history, &status)
+            {
+                history.finish(managed_dir, *status);
+            }
+            drop(in_use);
+            if config.shared_deps.after_build {
+                if let Some(managed_dir) = &managed_dir {
+                    // Failing to share artifacts shouldn't fail the build.
+                    if let Err(err) = share_deps_after_build(&config, managed_dir) {
+                        eprintln!("[targo] failed to share dependency artifacts: {err}");
+                    }
+                }
+            }
+            exit_with_status(status?);
+        }
+    }
 
     Ok(())
 }
 
+fn exec_store_move_dir(workspace: &Path, root: &str, message_format: MessageFormat) -> Result<()> {
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+    let workspace_dir = locate_workspace(workspace)?;
+
+    let store = TargoStore::new(store_dir, &config)?;
+    let report = store.move_dir(&workspace_dir, root)?;
+    if message_format == MessageFormat::Json {
+        print_json(report)?;
+    }
+    Ok(())
+}
+
+fn exec_store_sync(workspace: &Path, message_format: MessageFormat) -> Result<()> {
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+    let workspace_dir = locate_workspace(workspace)?;
+
+    let store = TargoStore::new(store_dir, &config)?;
+    let report = store.sync_snapshot(&workspace_dir)?;
+    if message_format == MessageFormat::Json {
+        print_json(report)?;
+    }
+    Ok(())
+}
+
+fn exec_status(workspace: Option<&Path>, message_format: MessageFormat) -> Result<()> {
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+    let workspace_dir = try_locate_workspace(workspace, None)?;
+
+    let report = match workspace_dir {
+        None => StatusReport {
+            workspace: None,
+            target_dir: None,
+            kind: None,
+            placement: config.placement,
+            excluded: false,
+            opted_out: false,
+            action: StatusAction::NotInWorkspace,
+            managed: None,
+        },
+        Some(workspace_dir) => {
+            let store = TargoStore::new(store_dir, &config)?;
+            // This matches WrapCargoArgs::new and exec_wrap_cargo.
+            let target_dir = workspace_dir.join("target");
+            let placement = config.placement_for(&workspace_dir);
+            let excluded = config.is_excluded(&workspace_dir);
+            let opted_out = store.is_opted_out(&workspace_dir);
+            let state = store.inspect_target_dir(&target_dir)?;
+
+            let kind = match &state {
+                TargetDirState::DoesNotExist => TargetDirKindName::DoesNotExist,
+                TargetDirState::Directory => TargetDirKindName::Directory,
+                TargetDirState::TargoSymlink { .. } => TargetDirKindName::TargoSymlink,
+                TargetDirState::Other => TargetDirKindName::Foreign,
+            };
+            let action = if excluded {
+                StatusAction::Excluded
+            } else if opted_out {
+                StatusAction::OptedOut
+            } else {
+                let explicit_target_dir = std::env::var_os("CARGO_TARGET_DIR").is_some();
+                planned_action(
+                    placement,
+                    &state,
+                    explicit_target_dir,
+                    is_writable(&workspace_dir),
+                )
+            };
+
+            let encoded = match state {
+                TargetDirState::TargoSymlink { encoded } => encoded,
+                _ => target_dir_key(&workspace_dir, &target_dir),
+            };
+            let managed = store
+                .managed_dir_info(&encoded)?
+                .map(|info| ManagedDirStatus {
+                    path: info.path,
+                    root: info.root,
+                    size: info.size,
+                    last_used: info.last_used,
+                });
+
+            StatusReport {
+                workspace: Some(workspace_dir),
+                target_dir: Some(target_dir),
+                kind: Some(kind),
+                placement,
+                excluded,
+                opted_out,
+                action,
+                managed,
+            }
+        }
+    };
+
+    match message_format {
+        MessageFormat::Human => print_status(&report),
+        MessageFormat::Json => print_json(report)?,
+    }
+    Ok(())
+}
+
+/// Returns what `targo wrap-cargo` does for a workspace that isn't excluded or ejected.
+fn planned_action(
+    placement: Placement,
+    state: &TargetDirState,
+    explicit_target_dir: bool,
+    writable: bool,
+) -> StatusAction {
+    match (placement, state) {
+        // Creating the symlink fails, so wrap-cargo falls back to CARGO_TARGET_DIR.
+        (Placement::Symlink, TargetDirState::DoesNotExist | TargetDirState::Directory)
+            if !writable =>
+        {
+            if explicit_target_dir {
+                StatusAction::ExplicitTargetDir
+            } else {
+                StatusAction::NotWritable
+            }
+        }
+        (Placement::Symlink, TargetDirState::DoesNotExist) => StatusAction::CreateSymlink,
+        (Placement::Symlink, TargetDirState::Directory) => StatusAction::ReplaceDirectory,
+        (Placement::Symlink, TargetDirState::TargoSymlink { .. }) => StatusAction::UseSymlink,
+        (Placement::Symlink, TargetDirState::Other) => StatusAction::LeaveAlone,
+        (Placement::Env, _) if explicit_target_dir => StatusAction::ExplicitTargetDir,
+        (Placement::Env, _) => StatusAction::SetTargetDirEnv,
+    }
+}
+
+fn print_status(report: &StatusReport) {
+    let Some(workspace_dir) = &report.workspace else {
+        println!("not within a Cargo workspace");
+        return;
+    };
+    println!("workspace:   {workspace_dir}");
+    if let (Some(target_dir), Some(kind)) = (&report.target_dir, report.kind) {
+        let kind = match kind {
+            TargetDirKindName::DoesNotExist => "does not exist",
+            TargetDirKindName::Directory => "plain directory",
+            TargetDirKindName::TargoSymlink => "targo symlink",
+            TargetDirKindName::Foreign => "not managed by targo",
+        };
+        println!("target dir:  {target_dir} ({kind})");
+    }
+    let placement = match report.placement {
+        Placement::Symlink => "symlink",
+        Placement::Env => "env",
+    };
+    println!("placement:   {placement}");
+    let yes_no = |value| if value { "yes" } else { "no" };
+    println!("excluded:    {}", yes_no(report.excluded));
+    println!("ejected:     {}", yes_no(report.opted_out));
+    println!("action:      {}", report.action.description());
+    match &report.managed {
+        Some(managed) => {
+            println!("managed dir: {} (root `{}`)", managed.path, managed.root);
+            println!("size:        {}", format_bytes(managed.size));
+            if let Some(last_used) = managed.last_used {
+                println!("last used:   {}", last_used.format("%Y-%m-%d %H:%M:%S"));
+            }
+        }
+        None => println!("managed dir: none"),
+    }
+}
+
+/// Sets up a workspace's managed target directory the same way `wrap-cargo` does, replacing an
+/// existing `target` directory. Returns `None` if targo doesn't manage the workspace.
+fn actualize_workspace(
+    store: &TargoStore,
+    config: &TargoConfig,
+    workspace_dir: &Utf8Path,
+) -> Result<Option<ManagedTargetDir>> {
+    if config.is_excluded(workspace_dir) || store.is_opted_out(workspace_dir) {
+        return Ok(None);
+    }
+    match config.placement_for(workspace_dir) {
+        Placement::Symlink => {
+            let kind = store.determine_target_dir(workspace_dir, &workspace_dir.join("target"))?;
+            store.actualize_kind(kind)
+        }
+        Placement::Env => Ok(Some(store.env_target_dir(workspace_dir)?)),
+    }
+}
+
+fn exec_ra_target_dir(workspace: Option<&Path>) -> Result<()> {
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+    let workspace_dir = locate_workspace(workspace.unwrap_or(Path::new(".")))?;
+    if config.is_excluded(&workspace_dir) {
+        bail!("workspace `{workspace_dir}` is excluded by config");
+    }
+    let store = TargoStore::new(store_dir, &config)?;
+    if store.is_opted_out(&workspace_dir) {
+        bail!("workspace `{workspace_dir}` was ejected, and isn't managed by targo");
+    }
+
+    // Only look up the managed directory, since editors may run this while a build is using the
+    // target directory.
+    let target_dir = workspace_dir.join("target");
+    let encoded = match store.inspect_target_dir(&target_dir)? {
+        TargetDirState::TargoSymlink { encoded } => encoded,
+        _ => target_dir_key(&workspace_dir, &target_dir),
+    };
+    let Some(ra_target_dir) = store.ra_target_dir(&encoded)? else {
+        bail!(
+            "workspace `{workspace_dir}` doesn't have a managed target directory yet, \
+             run a build with targo (or `targo adopt`) first"
+        );
+    };
+    println!("{ra_target_dir}");
+    Ok(())
+}
+
+fn exec_exec(args: Vec<OsString>) -> Result<()> {
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+    let workspace_dir = locate_workspace(Path::new("."))?;
+    let store = TargoStore::new(store_dir, &config)?;
+    let managed_dir = actualize_workspace(&store, &config, &workspace_dir)?;
+
+    let mut args = args.into_iter();
+    let program = args.next().expect("clap requires a command");
+    let mut command = CargoCli::program(program);
+    command.args(args);
+    command.env("TARGO_WORKSPACE", &workspace_dir);
+    command.env(TARGO_ACTIVE_ENV, &workspace_dir);
+    // An explicit CARGO_TARGET_DIR wins, just as it does with Cargo.
+    if let Some(managed_dir) = &managed_dir {
+        if std::env::var_os("CARGO_TARGET_DIR").is_none() {
+            command.env("CARGO_TARGET_DIR", managed_dir.target_dir());
+        }
+    }
+
+    match config.run_mode {
+        RunMode::Exec => command.run_or_exec(),
+        RunMode::Spawn => {
+            let in_use = managed_dir
+                .as_ref()
+                .map(|managed_dir| managed_dir.lock_in_use())
+                .transpose()?;
+            let status = command.spawn_and_wait();
+            drop(in_use);
+            exit_with_status(status?);
+        }
+    }
+}
+
+/// Exit codes for `targo target-dir`. Clap uses 2 for usage errors, and errors exit with 1.
+const EXIT_NOT_A_WORKSPACE: i32 = 3;
+const EXIT_EXCLUDED: i32 = 4;
+const EXIT_UNMANAGED: i32 = 5;
+
+fn exec_target_dir(workspace: Option<&Path>, print: TargetDirPrint) -> Result<()> {
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+    let dir = workspace.unwrap_or(Path::new("."));
+    let Some(workspace_dir) = try_locate_workspace(Some(dir), None)? else {
+        eprintln!(
+            "[targo] `{}` is not within a Cargo workspace",
+            dir.display()
+        );
+        std::process::exit(EXIT_NOT_A_WORKSPACE);
+    };
+    if config.is_excluded(&workspace_dir) {
+        eprintln!("[targo] workspace `{workspace_dir}` is excluded by config");
+        std::process::exit(EXIT_EXCLUDED);
+    }
+
+    let store = TargoStore::new(store_dir, &config)?;
+    let target_dir = workspace_dir.join("target");
+    let (encoded, symlinked) = match store.inspect_target_dir(&target_dir)? {
+        TargetDirState::TargoSymlink { encoded } => (encoded, true),
+        _ => (target_dir_key(&workspace_dir, &target_dir), false),
+    };
+    let location = if store.is_opted_out(&workspace_dir) {
+        None
+    } else {
+        store.managed_target_dir_path(&encoded)
+    };
+    let Some((root, managed_target_dir)) = location else {
+        eprintln!("[targo] workspace `{workspace_dir}` doesn't have a managed target directory");
+        std::process::exit(EXIT_UNMANAGED);
+    };
+
+    // Resolve symlinks in the store's own path too, e.g. if the Cargo home is a symlink.
+    let resolve = |path: &Utf8Path| path.canonicalize_utf8().unwrap_or_else(|_| path.to_owned());
+    match print {
+        TargetDirPrint::Managed => println!("{}", resolve(&managed_target_dir)),
+        TargetDirPrint::Symlink if symlinked => println!("{target_dir}"),
+        TargetDirPrint::Symlink => {
+            eprintln!(
+                "[targo] workspace `{workspace_dir}` uses CARGO_TARGET_DIR, \
+                 so `{target_dir}` isn't a symlink to the store"
+            );
+            std::process::exit(EXIT_UNMANAGED);
+        }
+        TargetDirPrint::Encoded => println!("{encoded}"),
+        TargetDirPrint::Root => println!("{}", resolve(root)),
+    }
+    Ok(())
+}
+
+fn exec_adopt(
+    paths: Vec<PathBuf>,
+    dry_run: bool,
+    target_dir_name: &Utf8Path,
+    message_format: MessageFormat,
+) -> Result<()> {
+    // Only allow paths within the workspace, and not the workspace root itself.
+    let within_workspace = target_dir_name
+        .components()
+        .all(|component| matches!(component, Utf8Component::Normal(_)));
+    if !within_workspace || target_dir_name.as_str().is_empty() {
+        bail!(
+            "target directory name `{target_dir_name}` must be a relative path within the \
+             workspace, without `.` or `..` components"
+        );
+    }
+    let paths = if paths.is_empty() {
+        vec![PathBuf::from(".")]
+    } else {
+        paths
+    };
+
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+    let mut store = TargoStore::new(store_dir, &config)?;
+
+    let mut workspaces = Vec::new();
+    for path in paths {
+        let workspace_dir = locate_workspace(&path)?;
+        let target_dir = workspace_dir.join(target_dir_name);
+        let placement = config.placement_for(&workspace_dir);
+
+        let action = if config.is_excluded(&workspace_dir) {
+            StatusAction::Excluded
+        } else {
+            let state = store.inspect_target_dir(&target_dir)?;
+            planned_action(placement, &state, false, is_writable(&workspace_dir))
+        };
+        let verb = if dry_run { "would" } else { "will" };
+        eprintln!("[targo] `{workspace_dir}`: {verb} {}", action.description());
+
+        let mut managed_path = None;
+        let acts = !matches!(action, StatusAction::Excluded | StatusAction::LeaveAlone);
+        if !dry_run && acts {
+            store = store.clear_opt_out(&workspace_dir)?;
+            let managed_dir = match placement {
+                Placement::Symlink => {
+                    let kind = store.determine_target_dir(&workspace_dir, &target_dir)?;
+                    store.actualize_kind(kind)?
+                }
+                Placement::Env => Some(store.env_target_dir(&workspace_dir)?),
+            };
+            managed_path = managed_dir.map(|managed_dir| managed_dir.target_dir().to_owned());
+        }
+
+        workspaces.push(AdoptedWorkspace {
+            workspace: workspace_dir,
+            target_dir,
+            action,
+            managed_path,
+        });
+    }
+
+    if message_format == MessageFormat::Json {
+        print_json(AdoptReport {
+            dry_run,
+            workspaces,
+        })?;
+    }
+    Ok(())
+}
+
+fn exec_eject(workspace: Option<&Path>, message_format: MessageFormat) -> Result<()> {
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+    let workspace_dir = locate_workspace(workspace.unwrap_or(Path::new(".")))?;
+
+    let store = TargoStore::new(store_dir, &config)?;
+    let report = store.eject(&workspace_dir)?;
+    if message_format == MessageFormat::Json {
+        print_json(report)?;
+    }
+    Ok(())
+}
+
+fn exec_migrate_tree(root: &Path, dry_run: bool, message_format: MessageFormat) -> Result<()> {
+    let root = root
+        .canonicalize()
+        .wrap_err_with(|| format!("failed to resolve `{}`", root.display()))?;
+    let root = Utf8PathBuf::try_from(root).wrap_err("directory is invalid UTF-8")?;
+
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+    let store = TargoStore::new(store_dir, &config)?;
+
+    let mut report = MigrateTreeReport {
+        dry_run,
+        migrated: Vec::new(),
+        skipped: Vec::new(),
+        total_size: 0,
+    };
+    for dir in find_candidates(&root)? {
+        // Only workspace roots own their `target` directory.
+        match try_locate_workspace(Some(dir.as_std_path()), None) {
+            Ok(Some(workspace_dir)) if workspace_dir == dir => {}
+            Ok(_) => continue,
+            Err(err) => {
+                eprintln!("[targo] skipping `{dir}`: {err}");
+                continue;
+            }
+        }
+
+        let target_dir = dir.join("target");
+        let reason = if config.is_excluded(&dir) {
+            Some(SkipReason::Excluded)
+        } else if store.is_opted_out(&dir) {
+            Some(SkipReason::OptedOut)
+        } else if config.placement_for(&dir) == Placement::Env {
+            Some(SkipReason::EnvPlacement)
+        } else {
+            match store.inspect_target_dir(&target_dir)? {
+                TargetDirState::Directory => None,
+                TargetDirState::TargoSymlink { .. } => Some(SkipReason::AlreadyManaged),
+                TargetDirState::DoesNotExist | TargetDirState::Other => Some(SkipReason::Foreign),
+            }
+        };
+
+        let reason = match reason {
+            Some(reason) => Some(reason),
+            None if dry_run => {
+                let size = dir_size(&target_dir)?;
+                eprintln!("[targo] would move `{target_dir}` ({})", format_bytes(size));
+                report.total_size += size;
+                report.migrated.push(MigratedWorkspace {
+                    workspace: dir.clone(),
+                    managed_path: None,
+                    size,
+                });
+                None
+            }
+            None => {
+                let managed_dir = store.migrate_target_dir(&dir)?;
+                if managed_dir.placement() == Placement::Env {
+                    Some(SkipReason::NotWritable)
+                } else {
+                    let size = managed_dir.size()?;
+                    eprintln!(
+                        "[targo] moved `{target_dir}` to `{}` ({})",
+                        managed_dir.target_dir(),
+                        format_bytes(size)
+                    );
+                    report.total_size += size;
+                    report.migrated.push(MigratedWorkspace {
+                        workspace: dir.clone(),
+                        managed_path: Some(managed_dir.target_dir().to_owned()),
+                        size,
+                    });
+                    None
+                }
+            }
+        };
+        if let Some(reason) = reason {
+            eprintln!("[targo] skipping `{dir}`: {}", reason.description());
+            report.skipped.push(SkippedWorkspace {
+                workspace: dir,
+                reason,
+            });
+        }
+    }
+
+    eprintln!(
+        "[targo] {} {} workspaces ({}), skipped {}",
+        if dry_run { "would migrate" } else { "migrated" },
+        report.migrated.len(),
+        format_bytes(report.total_size),
+        report.skipped.len()
+    );
+    if message_format == MessageFormat::Json {
+        print_json(report)?;
+    }
+    Ok(())
+}
+
+fn exec_share_deps(dry_run: bool, message_format: MessageFormat) -> Result<()> {
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+    if !config.shared_deps.enabled {
+        bail!(
+            "shared dependency artifacts are disabled, set `enabled = true` in the \
+             `[shared-deps]` section of `{}`",
+            store_dir.join(TargoConfig::CONFIG_FILE_NAME)
+        );
+    }
+
+    let store = TargoStore::new(store_dir, &config)?;
+    let report = store.share_deps(&registry_src_dir()?, dry_run)?;
+    let verb = if dry_run { "would save" } else { "saved" };
+    eprintln!(
+        "[targo] {} registry artifacts: {} already shared, {} stored, {} linked ({verb} {})",
+        report.scanned,
+        report.already_shared,
+        report.stored,
+        report.linked,
+        format_bytes(report.saved_size)
+    );
+    if message_format == MessageFormat::Json {
+        print_json(report)?;
+    }
+    Ok(())
+}
+
+fn share_deps_after_build(config: &TargoConfig, managed_dir: &ManagedTargetDir) -> Result<()> {
+    if !config.shared_deps.enabled {
+        return Ok(());
+    }
+    match managed_dir.share_deps(&registry_src_dir()?)? {
+        Some(report) => tracing::debug!("shared dependency artifacts after build: {report:?}"),
+        None => tracing::debug!("target directory is in use by another build, not sharing"),
+    }
+    Ok(())
+}
+
+/// Returns the directory that Cargo unpacks registry crates' sources into.
+fn registry_src_dir() -> Result<Utf8PathBuf> {
+    let dir = home::cargo_home().wrap_err("unable to determine cargo home dir")?;
+    let dir = Utf8PathBuf::try_from(dir).wrap_err("cargo home is invalid UTF-8")?;
+    Ok(dir.join("registry").join("src"))
+}
+
+fn exec_stats(unused_days: u32, top: usize, message_format: MessageFormat) -> Result<()> {
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+
+    let store = TargoStore::new(store_dir, &config)?;
+    let stats = store.stats(unused_days, top)?;
+    match message_format {
+        MessageFormat::Human => stats.print_human(),
+        MessageFormat::Json => print_json(stats)?,
+    }
+
+    Ok(())
+}
+
+fn exec_history(
+    workspace: Option<&Path>,
+    limit: Option<usize>,
+    message_format: MessageFormat,
+) -> Result<()> {
+    let store_dir = find_targo_store_dir()?;
+    let config = TargoConfig::load(&store_dir)?;
+    let workspace_dir = locate_workspace(workspace.unwrap_or(Path::new(".")))?;
+
+    let store = TargoStore::new(store_dir, &config)?;
+    let mut records = store.history(&workspace_dir)?;
+    let skip = limit.map_or(0, |limit| records.len().saturating_sub(limit));
+    records.drain(..skip);
+    if message_format == MessageFormat::Json {
+        return print_json(HistoryReport {
+            workspace: workspace_dir,
+            records,
+        });
+    }
+    if records.is_empty() {
+        match config.run_mode {
+            RunMode::Exec => eprintln!(
+                "[targo] no build history recorded for `{workspace_dir}`: history is only \
+                 recorded with `run-mode = \"spawn\"`"
+            ),
+            RunMode::Spawn => eprintln!("[targo] no build history recorded for `{workspace_dir}`"),
+        }
+        return Ok(());
+    }
+
+    for record in &records {
+        let status = match (record.exit_code, record.signal) {
+            (Some(0), _) => "ok".to_owned(),
+            (Some(code), _) => format!("exit {code}"),
+            (None, Some(signal)) => format!("signal {signal}"),
+            (None, None) => "unknown".to_owned(),
+        };
+        let size_change = if record.size_after >= record.size_before {
+            format!("+{}", format_bytes(record.size_after - record.size_before))
+        } else {
+            format!("-{}", format_bytes(record.size_before - record.size_after))
+        };
+        println!(
+            "{}  {status:<9}  {:>8.1}s  {:>10} ({size_change})  {}  cargo {}",
+            record.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            record.duration_ms as f64 / 1000.0,
+            format_bytes(record.size_after),
+            record.toolchain.as_deref().unwrap_or("unknown toolchain"),
+            shell_words::join(&record.args),
+        );
+    }
+
+    Ok(())
+}
+
+fn exec_json_schema(command: &str) -> Result<()> {
+    let schemas = json_schemas();
+    let Some((_, schema)) = schemas.iter().find(|(name, _)| *name == command) else {
+        let names: Vec<_> = schemas.iter().map(|(name, _)| *name).collect();
+        bail!(
+            "no JSON schema for command `{command}` (known commands: {})",
+            names.join(", ")
+        );
+    };
+    let json = serde_json::to_string_pretty(schema).wrap_err("failed to serialize schema")?;
+    println!("{json}");
+    Ok(())
+}
+
+fn exec_gc(dry_run: bool, max_age_days: Option<u32>, message_format: MessageFormat) -> Result<()> {
+    let store_dir = find_targo_store_dir()?;
+    let mut config = TargoConfig::load(&store_dir)?;
+    if let Some(max_age_days) = max_age_days {
+        config.gc.max_age_days = max_age_days;
+    }
+
+    let store = TargoStore::new(store_dir, &config)?;
+    let report = store.gc(&config.gc, dry_run)?;
+    if message_format == MessageFormat::Json {
+        print_json(report)?;
+    }
+    Ok(())
+}
+
+/// The state of a target directory when Cargo was started, for its history log.
+struct HistoryStart {
+    timestamp: chrono::DateTime<Local>,
+    started: Instant,
+    subcommand: Option<String>,
+    args: Vec<String>,
+    toolchain: Option<String>,
+    size_before: u64,
+    /// Whether to measure the target directory again once Cargo exits.
+    measure_after: bool,
+}
+
+impl HistoryStart {
+    fn new(managed_dir: &ManagedTargetDir, parsed_args: &ParsedCargoArgs) -> Self {
+        // Walking a large target directory is slow, so reuse the size measured after the
+        // previous build if there is one.
+        let last_size = managed_dir
+            .last_history()
+            .unwrap_or_else(|err| {
+                tracing::debug!("failed to read build history: {err}");
+                None
+            })
+            .map(|record| record.size_after);
+        let size_before = last_size.unwrap_or_else(|| {
+            managed_dir.size().unwrap_or_else(|err| {
+                tracing::debug!("failed to compute size of target dir: {err}");
+                0
+            })
+        });
+        Self {
+            timestamp: Local::now(),
+            started: Instant::now(),
+            subcommand: parsed_args
+                .subcommand
+                .as_ref()
+                .map(|subcommand| subcommand.to_string_lossy().into_owned()),
+            args: parsed_args
+                .args
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            toolchain: parsed_args.toolchain_version(managed_dir),
+            size_before,
+            measure_after: parsed_args.may_write_target_dir(),
+        }
+    }
+
+    /// Records the invocation in the target directory's history. Failures are reported but
+    /// don't affect the exit status.
+    fn finish(self, managed_dir: &ManagedTargetDir, status: ExitStatus) {
+        use std::os::unix::process::ExitStatusExt;
+
+        let duration = self.started.elapsed();
+        let size_after = if self.measure_after {
+            managed_dir.size().unwrap_or_else(|err| {
+                tracing::debug!("failed to compute size of target dir: {err}");
+                0
+            })
+        } else {
+            self.size_before
+        };
+        let record = HistoryRecord {
+            timestamp: self.timestamp,
+            subcommand: self.subcommand,
+            args: self.args,
+            toolchain: self.toolchain,
+            exit_code: status.code(),
+            signal: status.signal(),
+            duration_ms: duration.as_millis().try_into().unwrap_or(u64::MAX),
+            size_before: self.size_before,
+            size_after,
+        };
+        if let Err(err) = managed_dir.append_history(&record) {
+            eprintln!("[targo] failed to record build history: {err}");
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 enum WrapCargoArgs {
     Enabled {
@@ -77,48 +1258,63 @@ enum WrapCargoArgs {
         workspace_dir: Utf8PathBuf,
         target_dir: Utf8PathBuf,
     },
+    /// A build without a workspace, such as `cargo install` or a Cargo script, pointed at a
+    /// managed target directory via `CARGO_TARGET_DIR`.
+    Standalone {
+        parsed_args: ParsedCargoArgs,
+        used_for: TargetDirUse,
+    },
     Disabled {
         parsed_args: ParsedCargoArgs,
     },
 }
 
 impl WrapCargoArgs {
-    fn new(parser: lexopt::Parser) -> Result<Self> {
+    fn new(args: Vec<OsString>, config: &TargoConfig) -> Result<Self> {
         // TODO: intercept cargo clean -- it doesn't work right now, it should clean the symlink
         // target.
 
-        let parsed_args = ParsedCargoArgs::from_parser(parser)
-            .with_context(|| "error parsing Cargo arguments")?;
+        let parsed_args =
+            ParsedCargoArgs::new(args).with_context(|| "error parsing Cargo arguments")?;
 
-        // Determine the workspace dir.
-        let mut locate_project = CargoCli::new();
-        locate_project.args(["locate-project", "--workspace", "--message-format=plain"]);
-        if let Some(manifest_path) = &parsed_args.manifest_path {
-            locate_project.arg("--manifest-path");
-            locate_project.arg(manifest_path);
+        if parsed_args.subcommand.as_deref() == Some(OsStr::new("install")) {
+            if !config.install.cache || parsed_args.has_explicit_target_dir() {
+                return Ok(Self::Disabled { parsed_args });
+            }
+            return match parsed_args.install_source() {
+                Some(source) => Ok(Self::Standalone {
+                    parsed_args,
+                    used_for: TargetDirUse::Install(source),
+                }),
+                None => Ok(Self::Disabled { parsed_args }),
+            };
         }
 
-        let output = match locate_project.stdout_output() {
-            Ok(output) => output,
-            Err(_) => {
-                eprintln!("[targo] error running cargo locate-project, disabling");
+        // Scripts don't have a workspace directory, so locate-project isn't meaningful for them.
+        if let Some(path) = parsed_args.script_path() {
+            if parsed_args.has_explicit_target_dir() {
                 return Ok(Self::Disabled { parsed_args });
             }
+            return Ok(Self::Standalone {
+                parsed_args,
+                used_for: TargetDirUse::Script { path },
+            });
+        }
+
+        // Determine the workspace dir.
+        let Some(workspace_dir) = try_locate_workspace(
+            parsed_args.directory.as_deref(),
+            parsed_args.manifest_path.as_deref(),
+        )?
+        else {
+            eprintln!("[targo] error running cargo locate-project, disabling");
+            return Ok(Self::Disabled { parsed_args });
         };
 
-        let mut locate_project_output = String::from_utf8(output)
-            .wrap_err_with(|| format!("`{locate_project}` produced invalid UTF-8 output"))?;
-        // Last character of workspace_dir_str must be a newline.
-        if !locate_project_output.ends_with('\n') {
-            bail!("`{locate_project}` produced output not terminated with a newline: {locate_project_output}");
-        }
-        locate_project_output.pop();
-        let mut workspace_dir = Utf8PathBuf::from(locate_project_output);
-        // The filename of workspace dir should be Cargo.toml.
-        if workspace_dir.file_name() != Some("Cargo.toml") {
-            bail!("cargo locate-project output `{workspace_dir}` doesn't end with Cargo.toml");
+        if config.is_excluded(&workspace_dir) {
+            tracing::debug!("workspace {workspace_dir} is excluded by config, disabling");
+            return Ok(Self::Disabled { parsed_args });
         }
-        workspace_dir.pop();
 
         // TODO: read --target-dir/build.target-dir from cargo.
         let target_dir = workspace_dir.join("target");
@@ -129,27 +1325,141 @@ impl WrapCargoArgs {
             target_dir,
         })
     }
+
+    /// The value of [`TARGO_ACTIVE_ENV`] for this build, if targo manages its target directory.
+    ///
+    /// Workspace paths are absolute, so they can't be confused with standalone keys.
+    fn active_marker(&self) -> Option<String> {
+        match self {
+            Self::Enabled { workspace_dir, .. } => Some(workspace_dir.to_string()),
+            Self::Standalone { used_for, .. } => Some(used_for.key()),
+            Self::Disabled { .. } => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 struct ParsedCargoArgs {
-    cli_args: Vec<OsString>,
-    post_double_hyphen: Vec<OsString>,
+    /// The original arguments, forwarded to Cargo byte-for-byte.
+    args: Vec<OsString>,
+    /// The rustup toolchain override passed in as `+toolchain`, if any.
+    toolchain: Option<String>,
+    /// The directory passed in via `-C`, if any.
+    directory: Option<PathBuf>,
     manifest_path: Option<PathBuf>,
+    subcommand: Option<OsString>,
+    /// Free arguments after the subcommand, not including anything after `--`.
+    positionals: Vec<OsString>,
+    /// Values for options in [`Self::LONG_WITH_VALUE`], in the order they were seen.
+    option_values: Vec<(String, OsString)>,
 }
 
 impl ParsedCargoArgs {
-    fn from_parser(mut parser: lexopt::Parser) -> Result<Self> {
-        let mut seen_double_hyphen = false;
-        let mut cli_args = Vec::new();
-        let mut post_double_hyphen = Vec::new();
+    /// Short options that take a value, e.g. `-p foo` or `-pfoo`.
+    const SHORT_WITH_VALUE: &'static [char] = &['p', 'j', 'F', 'Z', 'C'];
+
+    /// Long options that take a value, e.g. `--package foo` or `--package=foo`.
+    ///
+    /// Unknown long options are assumed to only take a value if it's provided via `=`. `--version`
+    /// is handled separately, since it only takes a value for `cargo install`.
+    const LONG_WITH_VALUE: &'static [&'static str] = &[
+        "manifest-path",
+        "package",
+        "exclude",
+        "jobs",
+        "features",
+        "target",
+        "target-dir",
+        "profile",
+        "config",
+        "color",
+        "message-format",
+        "explain",
+        "lockfile-path",
+        "registry",
+        "index",
+        "git",
+        "branch",
+        "tag",
+        "rev",
+        "path",
+        "root",
+    ];
+
+    /// Long options whose value is optional: without one, Cargo lists the available targets. They
+    /// take a value via `=`, or from the next argument if it doesn't start with `-`.
+    const LONG_WITH_OPTIONAL_VALUE: &'static [&'static str] = &["bin", "example", "test", "bench"];
+
+    /// Subcommands that don't write to the target directory.
+    const READ_ONLY_SUBCOMMANDS: &'static [&'static str] = &[
+        "add",
+        "fetch",
+        "fmt",
+        "generate-lockfile",
+        "help",
+        "info",
+        "locate-project",
+        "metadata",
+        "pkgid",
+        "read-manifest",
+        "remove",
+        "search",
+        "tree",
+        "update",
+        "verify-project",
+        "version",
+    ];
+
+    /// Analyzes the arguments passed in.
+    ///
+    /// The arguments are only inspected: they are passed through to Cargo unchanged, since
+    /// reconstructed forms (e.g. `-p=foo` for `-pfoo`) aren't always equivalent for Cargo and
+    /// external subcommands.
+    fn new(args: Vec<OsString>) -> Result<Self> {
+        // Everything after `--` is passed through literally, so only analyze the arguments
+        // before it.
+        let analyzed_len = args
+            .iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(args.len());
+        // rustup's `+toolchain` override is only recognized as the first argument.
+        let toolchain = args
+            .first()
+            .and_then(|arg| arg.to_str()?.strip_prefix('+'))
+            .map(str::to_owned);
+        let analyzed_start = usize::from(toolchain.is_some()).min(analyzed_len);
+        let mut parser =
+            lexopt::Parser::from_args(args[analyzed_start..analyzed_len].iter().cloned());
+
+        let mut directory = None;
         let mut manifest_path = None;
-        while let Some(arg) = parser.next()? {
+        let mut subcommand = None;
+        let mut positionals = Vec::new();
+        let mut option_values = Vec::new();
+        loop {
+            let arg = match parser.next() {
+                Ok(Some(arg)) => arg,
+                Ok(None) => break,
+                // A value attached to a flag (e.g. `-v=2`) is for Cargo to interpret.
+                Err(lexopt::Error::UnexpectedValue { .. }) => continue,
+                Err(err) => return Err(err.into()),
+            };
             match arg {
+                // -C is a global option, so it's only meaningful before the subcommand.
+                Short('C') if subcommand.is_none() => {
+                    if let Ok(value) = parser.value() {
+                        tracing::debug!("setting directory to {}", Path::new(&value).display());
+                        directory = Some(PathBuf::from(value));
+                    }
+                }
                 Long("manifest-path") => {
                     // manifest-path can't be specified multiple times
                     let new_manifest_path = match &manifest_path {
-                        None => parser.value()?,
+                        None => match parser.value() {
+                            Ok(value) => value,
+                            // Let Cargo report the missing value.
+                            Err(_) => continue,
+                        },
                         Some(_) => {
                             return Err(lexopt::Error::Custom(
                                 "error: The argument '--manifest-path <PATH>' was provided \
@@ -159,73 +1469,307 @@ impl ParsedCargoArgs {
                             .into());
                         }
                     };
-                    manifest_path = Some(PathBuf::from(new_manifest_path.clone()));
                     tracing::debug!(
                         "setting manifest-path to {}",
                         Path::new(&new_manifest_path).display()
                     );
-
-                    // Also pass through the manifest path to the underlying cargo command.
-                    cli_args.extend(["--manifest-path".into(), new_manifest_path]);
+                    manifest_path = Some(PathBuf::from(new_manifest_path));
                 }
-                Long(other) => {
+                Long(other)
+                    if Self::LONG_WITH_VALUE.contains(&other)
+                        || (other == "version"
+                            && subcommand.as_deref() == Some(OsStr::new("install"))) =>
+                {
                     let other = other.to_owned();
-                    if let Some(val) = parser.optional_value() {
-                        tracing::debug!("long arg: {other} with optional value: {val:?}");
-                        let mut arg = OsString::from(format!("--{other}="));
-                        arg.push(&val);
-                        cli_args.push(arg);
-                    } else {
-                        tracing::debug!("long arg: {other} without optional value");
-                        cli_args.push(format!("--{other}").into());
+                    // A missing value is an error for Cargo to report, not us.
+                    let val = parser.value().ok();
+                    tracing::debug!("long arg: {other} with value: {val:?}");
+                    if let Some(val) = val {
+                        option_values.push((other, val));
                     }
                 }
-                Short(arg) => {
-                    if let Some(val) = parser.optional_value() {
-                        tracing::debug!("short arg: {arg} with optional value: {val:?}");
-                        let mut arg = OsString::from(format!("-{arg}="));
-                        arg.push(&val);
-                        cli_args.push(arg);
-                    } else {
-                        tracing::debug!("short arg: {arg} without optional value");
-                        cli_args.push(format!("-{arg}").into());
+                Long(other) if Self::LONG_WITH_OPTIONAL_VALUE.contains(&other) => {
+                    let other = other.to_owned();
+                    let val = parser.optional_value().or_else(|| {
+                        parser
+                            .raw_args()
+                            .ok()?
+                            .next_if(|arg| !arg.as_encoded_bytes().starts_with(b"-"))
+                    });
+                    tracing::debug!("long arg: {other} with optional value: {val:?}");
+                    if let Some(val) = val {
+                        option_values.push((other, val));
                     }
                 }
+                Long(other) => {
+                    let other = other.to_owned();
+                    let val = parser.optional_value();
+                    tracing::debug!("long arg: {other} with optional value: {val:?}");
+                }
+                Short(arg) if Self::SHORT_WITH_VALUE.contains(&arg) => {
+                    let val = parser.value().ok();
+                    tracing::debug!("short arg: {arg} with value: {val:?}");
+                }
+                Short(arg) => {
+                    tracing::debug!("short arg: {arg}");
+                }
                 Value(value) => {
-                    if seen_double_hyphen {
-                        tracing::debug!(
-                            "argument {value:?}, post-double-hyphen so treating literally"
-                        );
-                        post_double_hyphen.push(value);
+                    if subcommand.is_none() {
+                        tracing::debug!("subcommand {value:?}");
+                        subcommand = Some(value);
                     } else {
                         tracing::debug!("argument {value:?}");
-                        cli_args.push(value);
+                        positionals.push(value);
                     }
                 }
             }
-            if parser.raw_args()?.peek() == Some(OsStr::new("--")) {
-                seen_double_hyphen = true;
-            }
         }
 
         Ok(Self {
-            cli_args,
-            post_double_hyphen,
+            args,
+            toolchain,
+            directory,
             manifest_path,
+            subcommand,
+            positionals,
+            option_values,
+        })
+    }
+
+    /// Returns the last value passed in for the given long option.
+    fn option_value(&self, name: &str) -> Option<&OsStr> {
+        self.option_values
+            .iter()
+            .rev()
+            .find(|(option, _)| option == name)
+            .map(|(_, value)| value.as_os_str())
+    }
+
+    /// Returns true if the target directory was set through the environment or command line.
+    fn has_explicit_target_dir(&self) -> bool {
+        std::env::var_os("CARGO_TARGET_DIR").is_some() || self.option_value("target-dir").is_some()
+    }
+
+    /// For single-file Cargo scripts (`cargo foo.rs` or `cargo -Zscript foo.rs`), returns the
+    /// absolute path to the script.
+    fn script_path(&self) -> Option<Utf8PathBuf> {
+        let subcommand = Path::new(self.subcommand.as_ref()?);
+        // This matches the heuristic Cargo uses to tell scripts apart from subcommands.
+        if subcommand.components().count() <= 1 && subcommand.extension() != Some(OsStr::new("rs"))
+        {
+            return None;
+        }
+
+        let path = match &self.directory {
+            Some(directory) => directory.join(subcommand),
+            None => subcommand.to_owned(),
+        };
+        // If the script doesn't exist, Cargo will produce an error.
+        let path = path.canonicalize().ok()?;
+        if !path.is_file() {
+            return None;
+        }
+        Utf8PathBuf::try_from(path).ok()
+    }
+
+    /// Returns true if these arguments are for the workspace or standalone build that `marker`
+    /// (the value of [`TARGO_ACTIVE_ENV`]) was set for.
+    ///
+    /// This is checked before loading the config or running `cargo locate-project`, so that
+    /// nested invocations stay cheap. Instead of locating the workspace, the directory Cargo
+    /// starts looking from is checked to be within the marked workspace.
+    fn is_nested_in(&self, marker: &str) -> bool {
+        if let Some(path) = self.script_path() {
+            return TargetDirUse::Script { path }.key() == marker;
+        }
+        if self.subcommand.as_deref() == Some(OsStr::new("install")) {
+            return self
+                .install_source()
+                .is_some_and(|source| source.key() == marker);
+        }
+
+        let marker = Path::new(marker);
+        if !marker.is_absolute() {
+            return false;
+        }
+        let mut start_dir = match &self.directory {
+            Some(directory) => directory.clone(),
+            None => PathBuf::from("."),
+        };
+        if let Some(manifest_path) = &self.manifest_path {
+            start_dir = start_dir.join(manifest_path);
+            start_dir.pop();
+        }
+        start_dir
+            .canonicalize()
+            .is_ok_and(|start_dir| start_dir.starts_with(marker))
+    }
+
+    /// For `cargo install`, returns the crates being installed and where they're installed
+    /// from.
+    ///
+    /// Returns `None` if nothing is built, e.g. for `cargo install --list`, or if the source can't
+    /// be represented, e.g. because of non-UTF-8 arguments.
+    fn install_source(&self) -> Option<InstallSource> {
+        // Without crates, only `--path` and `--git` sources build anything.
+        if self.positionals.is_empty()
+            && self.option_value("path").is_none()
+            && self.option_value("git").is_none()
+        {
+            return None;
+        }
+
+        let source = if let Some(path) = self.option_value("path") {
+            // Resolve the path relative to -C, the same way Cargo does.
+            let path = match &self.directory {
+                Some(directory) => directory.join(path),
+                None => PathBuf::from(path),
+            };
+            let path = std::path::absolute(&path).ok()?;
+            let path = path.canonicalize().unwrap_or(path);
+            format!("path+{}", path.to_str()?)
+        } else if let Some(git) = self.option_value("git") {
+            format!("git+{}", git.to_str()?)
+        } else if let Some(index) = self.option_value("index") {
+            format!("index+{}", index.to_str()?)
+        } else if let Some(registry) = self.option_value("registry") {
+            format!("registry+{}", registry.to_str()?)
+        } else {
+            "registry+crates-io".to_owned()
+        };
+
+        let crates = self
+            .positionals
+            .iter()
+            .map(|krate| {
+                // Strip version requirements like `foo@1.2`, so that upgrades reuse the same
+                // target directory.
+                let krate = krate.to_str()?;
+                Some(
+                    krate
+                        .split_once('@')
+                        .map_or(krate, |(name, _)| name)
+                        .to_owned(),
+                )
+            })
+            .collect::<Option<_>>()?;
+
+        Some(InstallSource { source, crates })
+    }
+
+    /// Returns false if Cargo is known not to write to the target directory, e.g. for
+    /// `cargo metadata` or `cargo --version`.
+    fn may_write_target_dir(&self) -> bool {
+        self.subcommand.as_ref().is_some_and(|subcommand| {
+            !Self::READ_ONLY_SUBCOMMANDS.contains(&&*subcommand.to_string_lossy())
         })
     }
 
+    /// Returns the version of the toolchain that Cargo will use, e.g. `rustc 1.81.0 (...)`.
+    ///
+    /// Running `rustc -V` can take a noticeable amount of time, so its output is cached in the
+    /// managed directory for a while. Falls back to the toolchain name if `rustc` can't be run.
+    fn toolchain_version(&self, managed_dir: &ManagedTargetDir) -> Option<String> {
+        // Everything that affects which toolchain rustup picks, other than toolchain files.
+        let key = format!(
+            "{:?} {:?} {:?} {:?}",
+            self.toolchain,
+            std::env::var_os("RUSTUP_TOOLCHAIN"),
+            std::env::var_os("RUSTC"),
+            self.directory,
+        );
+        if let Some(version) = managed_dir.cached_toolchain_version(&key) {
+            return Some(version);
+        }
+        if let Some(version) = self.rustc_version() {
+            if let Err(err) = managed_dir.cache_toolchain_version(key, version.clone()) {
+                tracing::debug!("failed to cache toolchain version: {err}");
+            }
+            return Some(version);
+        }
+        self.toolchain
+            .clone()
+            .or_else(|| std::env::var("RUSTUP_TOOLCHAIN").ok())
+    }
+
+    /// Runs `rustc -V` the way Cargo would run rustc.
+    fn rustc_version(&self) -> Option<String> {
+        let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        let mut command = Command::new(rustc);
+        if let Some(toolchain) = &self.toolchain {
+            command.arg(format!("+{toolchain}"));
+        }
+        command.arg("-V");
+        // Run from the same directory as Cargo, so that rust-toolchain.toml files are honored.
+        if let Some(directory) = &self.directory {
+            command.current_dir(directory);
+        }
+
+        command
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|version| version.trim().to_owned())
+    }
+
     fn cargo_command(&self) -> CargoCli {
         let mut cli = CargoCli::new();
-        cli.args(&self.cli_args);
-        if !self.post_double_hyphen.is_empty() {
-            cli.arg("--");
-            cli.args(&self.post_double_hyphen);
-        }
+        cli.args(&self.args);
         cli
     }
 }
 
+/// Runs `cargo locate-project` to find the workspace root directory.
+///
+/// Returns `None` if `cargo locate-project` fails, e.g. because there's no workspace.
+fn try_locate_workspace(
+    directory: Option<&Path>,
+    manifest_path: Option<&Path>,
+) -> Result<Option<Utf8PathBuf>> {
+    let mut locate_project = CargoCli::new();
+    locate_project.args(["locate-project", "--workspace", "--message-format=plain"]);
+    // Run locate-project from the directory passed in via -C, so that both the workspace
+    // and a relative --manifest-path are resolved the same way Cargo will resolve them.
+    if let Some(directory) = directory {
+        locate_project.current_dir(directory);
+    }
+    if let Some(manifest_path) = manifest_path {
+        locate_project.arg("--manifest-path");
+        locate_project.arg(manifest_path);
+    }
+
+    let output = match locate_project.stdout_output() {
+        Ok(output) => output,
+        Err(err) => {
+            tracing::debug!("error running cargo locate-project: {err}");
+            return Ok(None);
+        }
+    };
+
+    let mut locate_project_output = String::from_utf8(output)
+        .wrap_err_with(|| format!("`{locate_project}` produced invalid UTF-8 output"))?;
+    // Last character of workspace_dir_str must be a newline.
+    if !locate_project_output.ends_with('\n') {
+        bail!("`{locate_project}` produced output not terminated with a newline: {locate_project_output}");
+    }
+    locate_project_output.pop();
+    let mut workspace_dir = Utf8PathBuf::from(locate_project_output);
+    // The filename of workspace dir should be Cargo.toml.
+    if workspace_dir.file_name() != Some("Cargo.toml") {
+        bail!("cargo locate-project output `{workspace_dir}` doesn't end with Cargo.toml");
+    }
+    workspace_dir.pop();
+
+    Ok(Some(workspace_dir))
+}
+
+/// Finds the root directory of the workspace containing `dir`.
+fn locate_workspace(dir: &Path) -> Result<Utf8PathBuf> {
+    try_locate_workspace(Some(dir), None)?
+        .ok_or_else(|| eyre!("`{}` is not within a Cargo workspace", dir.display()))
+}
+
 fn find_targo_store_dir() -> Result<Utf8PathBuf> {
     let dir = home::cargo_home().wrap_err("unable to determine cargo home dir")?;
     let mut utf8_dir: Utf8PathBuf = dir
@@ -239,22 +1783,49 @@ fn find_targo_store_dir() -> Result<Utf8PathBuf> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+    use std::os::unix::ffi::OsStringExt;
+
+    #[test]
+    fn test_command() {
+        TargoApp::command().debug_assert();
+    }
+
+    #[test]
+    fn test_completions() {
+        for shell in clap_complete::Shell::value_variants() {
+            let mut out = Vec::new();
+            clap_complete::generate(*shell, &mut TargoApp::command(), "targo", &mut out);
+            let out = String::from_utf8(out).expect("completions are UTF-8");
+            assert!(
+                out.contains("message-format"),
+                "{shell} completions include flags"
+            );
+        }
+    }
 
     #[test]
     fn test_parse_wrap_cargo_args() -> Result<()> {
         let data = [
             "build -p foo",
             "build -p=foo",
+            "build -pfoo",
+            "build -vv",
             "test",
             "clippy --package baz --manifest-path test",
             "clippy --package=baz",
+            "clippy --package baz --manifest-path=test",
             "check --all-targets -- -Dwarnings",
             "run --package baz -- -- arg1 arg2",
+            "nextest run --profile ci -E 'test(foo)'",
+            "+nightly build -p foo",
+            "--version build",
+            "--version -vv",
+            "install --version 1.2.3 ripgrep",
         ];
         for input in data {
             let input_args = shell_words::split(input)?;
-            let parser = lexopt::Parser::from_args(input_args.clone());
-            let args = ParsedCargoArgs::from_parser(parser)?;
+            let args = ParsedCargoArgs::new(input_args.iter().map(OsString::from).collect())?;
 
             let cargo_command = args.cargo_command();
             let output: Vec<_> = cargo_command
@@ -267,4 +1838,244 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_parse_optional_values() -> Result<()> {
+        let data = [
+            ("run --bin foo --release", Some("foo"), &[][..], false),
+            ("run --bin=foo", Some("foo"), &[], false),
+            ("run --bin --release", None, &[], false),
+            ("run --bin", None, &[], false),
+            ("test --bin --target-dir /tmp/t", None, &[], true),
+            (
+                "test --bin --target-dir=/tmp/t filter",
+                None,
+                &["filter"],
+                true,
+            ),
+            ("test --bin foo filter", Some("foo"), &["filter"], false),
+            ("run --bin -- --target-dir x", None, &[], false),
+        ];
+        for (input, bin, positionals, explicit_target_dir) in data {
+            let input_args = shell_words::split(input)?;
+            let args = ParsedCargoArgs::new(input_args.iter().map(OsString::from).collect())?;
+            assert_eq!(
+                args.subcommand.as_deref(),
+                input_args.first().map(OsStr::new),
+                "subcommand matches for {input}"
+            );
+            assert_eq!(
+                args.option_value("bin"),
+                bin.map(OsStr::new),
+                "bin matches for {input}"
+            );
+            assert_eq!(
+                args.positionals,
+                positionals.iter().map(OsString::from).collect::<Vec<_>>(),
+                "positionals match for {input}"
+            );
+            assert_eq!(
+                args.option_value("target-dir").is_some(),
+                explicit_target_dir,
+                "explicit target dir matches for {input}"
+            );
+        }
+        for option in ["example", "test", "bench"] {
+            let input = format!("test --{option} --release");
+            let input_args = shell_words::split(&input)?;
+            let args = ParsedCargoArgs::new(input_args.iter().map(OsString::from).collect())?;
+            assert_eq!(args.option_value(option), None, "no value for {input}");
+            assert!(args.positionals.is_empty(), "no positionals for {input}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_directory() -> Result<()> {
+        let data = [
+            ("build", None),
+            ("-C ../other build", Some("../other")),
+            ("-C../other build", Some("../other")),
+            ("-v -C /abs/path check -p foo", Some("/abs/path")),
+            ("--config foo=bar -C dir test", Some("dir")),
+            // -C after the subcommand isn't Cargo's global option.
+            ("rustc -C opt-level=3", None),
+        ];
+        for (input, directory) in data {
+            let input_args = shell_words::split(input)?;
+            let args = ParsedCargoArgs::new(input_args.iter().map(OsString::from).collect())?;
+            assert_eq!(
+                args.directory.as_deref(),
+                directory.map(Path::new),
+                "directory matches for {input}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_toolchain() -> Result<()> {
+        let data = [
+            ("build", None, Some("build")),
+            ("+nightly build", Some("nightly"), Some("build")),
+            ("+1.81.0 -C dir test", Some("1.81.0"), Some("test")),
+            // Only the first argument is a toolchain override.
+            ("run -- +foo", None, Some("run")),
+            // `--version` is a flag outside of `cargo install`.
+            ("--version build", None, Some("build")),
+            ("--version -vv", None, None),
+            ("+nightly --version check", Some("nightly"), Some("check")),
+        ];
+        for (input, toolchain, subcommand) in data {
+            let input_args = shell_words::split(input)?;
+            let args = ParsedCargoArgs::new(input_args.iter().map(OsString::from).collect())?;
+            assert_eq!(
+                args.toolchain.as_deref(),
+                toolchain,
+                "toolchain matches for {input}"
+            );
+            assert_eq!(
+                args.subcommand.as_deref(),
+                subcommand.map(OsStr::new),
+                "subcommand matches for {input}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_install_source() -> Result<()> {
+        let data = [
+            ("install ripgrep", "registry+crates-io", &["ripgrep"][..]),
+            ("install ripgrep@14.1.0", "registry+crates-io", &["ripgrep"]),
+            (
+                "install --version 14.1.0 ripgrep",
+                "registry+crates-io",
+                &["ripgrep"],
+            ),
+            (
+                "install --locked cargo-nextest bat",
+                "registry+crates-io",
+                &["bat", "cargo-nextest"],
+            ),
+            (
+                "install --registry my-registry foo",
+                "registry+my-registry",
+                &["foo"],
+            ),
+            (
+                "install --git https://github.com/sunshowers/targo --bin targo",
+                "git+https://github.com/sunshowers/targo",
+                &[],
+            ),
+            ("install --path /some/crate", "path+/some/crate", &[]),
+        ];
+        for (input, source, crates) in data {
+            let input_args = shell_words::split(input)?;
+            let args = ParsedCargoArgs::new(input_args.iter().map(OsString::from).collect())?;
+            let install_source = args.install_source().expect("install source is valid");
+            assert_eq!(install_source.source, source, "source matches for {input}");
+            assert_eq!(
+                install_source.crates.iter().collect::<Vec<_>>(),
+                crates.iter().collect::<Vec<_>>(),
+                "crates match for {input}"
+            );
+        }
+
+        for input in ["install --list", "install --registry my-registry --list"] {
+            let input_args = shell_words::split(input)?;
+            let args = ParsedCargoArgs::new(input_args.iter().map(OsString::from).collect())?;
+            assert_eq!(args.install_source(), None, "nothing is built for {input}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_script_path() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        std::fs::write(temp_dir.path().join("foo.rs"), "fn main() {}\n")?;
+        let dir = temp_dir.path().canonicalize()?;
+        let script = dir.join("foo.rs");
+
+        let data = [
+            (
+                vec!["-C".into(), dir.clone().into_os_string(), "foo.rs".into()],
+                true,
+            ),
+            (
+                vec!["-Zscript".into(), script.clone().into_os_string()],
+                true,
+            ),
+            (
+                vec![script.clone().into_os_string(), "--".into(), "arg".into()],
+                true,
+            ),
+            (vec!["build".into()], false),
+            (vec![dir.join("missing.rs").into_os_string()], false),
+        ];
+        for (input, is_script) in data {
+            let args = ParsedCargoArgs::new(input.clone())?;
+            let expected = is_script.then(|| Utf8PathBuf::try_from(script.clone()).unwrap());
+            assert_eq!(
+                args.script_path(),
+                expected,
+                "script path matches for {input:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    fn arg_strategy() -> impl Strategy<Value = OsString> {
+        prop_oneof![
+            prop::sample::select(vec![
+                "--",
+                "-",
+                "-p",
+                "-pfoo",
+                "-p=foo",
+                "-vv",
+                "-Zscript",
+                "-C",
+                "--manifest-path",
+                "--manifest-path=Cargo.toml",
+                "--package",
+                "--package=foo",
+                "--config",
+                "-C",
+                "../other",
+                "build",
+                "run",
+            ])
+            .prop_map(OsString::from),
+            "[-=a-zA-Z0-9_./ ]{0,12}".prop_map(OsString::from),
+            prop::collection::vec(any::<u8>(), 0..12).prop_map(OsString::from_vec),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_args_passed_through_unchanged(
+            input in prop::collection::vec(arg_strategy(), 0..16),
+        ) {
+            match ParsedCargoArgs::new(input.clone()) {
+                Ok(args) => {
+                    let cargo_command = args.cargo_command();
+                    prop_assert_eq!(cargo_command.get_args(), &input[..]);
+                }
+                Err(_) => {
+                    // The only error we produce is for a repeated --manifest-path.
+                    let count = input
+                        .iter()
+                        .filter(|arg| arg.as_encoded_bytes().starts_with(b"--manifest-path"))
+                        .count();
+                    prop_assert!(count >= 2, "unexpected error for input {:?}", input);
+                }
+            }
+        }
+    }
 }
@@ -5,8 +5,10 @@ use color_eyre::{eyre::Context, Result};
 use fs2::FileExt;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashSet,
     fmt, fs,
-    io::{self, Write},
+    io::{self, BufRead, Write},
+    os::unix::fs::MetadataExt,
 };
 
 #[derive(Debug)]
@@ -47,8 +49,22 @@ impl<T: AsLockedCtx> UnlockedRoot<T> {
         })
     }
 
+    /// Obtains the exclusive lock if it's immediately available, returning `None` if the lock is
+    /// held elsewhere.
+    pub(crate) fn try_lock_exclusive(self) -> Result<Option<ExclusiveRoot<T>>> {
+        match self.file.try_lock_exclusive() {
+            Ok(()) => Ok(Some(ExclusiveRoot {
+                file: self.file,
+                ctx: self.ctx,
+            })),
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => Ok(None),
+            Err(err) => Err(err).wrap_err_with(|| {
+                format!("failed to obtain exclusive lock at `{}`", self.lock_path)
+            }),
+        }
+    }
+
     #[inline]
-    #[allow(dead_code)]
     pub(crate) fn lock_shared(self) -> Result<SharedRoot<T>> {
         self.file
             .lock_shared()
@@ -75,7 +91,6 @@ pub(crate) struct SharedRoot<T> {
 
 impl<T> SharedRoot<T> {
     /// Unlock this directory.
-    #[allow(dead_code)]
     pub(crate) fn unlock(self) -> T {
         self.ctx
     }
@@ -160,3 +175,285 @@ impl DirWithPath {
         Ok(())
     }
 }
+
+impl DirWithPath {
+    /// Appends a record to a JSON Lines file, creating it if necessary.
+    ///
+    /// Each record is written with a single `write` call to a file opened for appending, so
+    /// concurrent writers don't interleave records.
+    pub(crate) fn append_jsonl<T>(&self, file_name: &str, record: &T) -> Result<()>
+    where
+        T: Serialize + fmt::Debug,
+    {
+        let mut line = serde_json::to_string(record)
+            .wrap_err_with(|| format!("failed to serialize record {record:?}"))?;
+        line.push('\n');
+
+        let path = self.path.join(file_name);
+        let mut open_opts = cap_std::fs::OpenOptions::new();
+        open_opts.append(true).create(true);
+        let mut file = self
+            .dir
+            .open_with(file_name, &open_opts)
+            .wrap_err_with(|| format!("failed to open `{path}`"))?;
+        file.write_all(line.as_bytes())
+            .wrap_err_with(|| format!("failed to append to `{path}`"))
+    }
+
+    /// Reads all records from a JSON Lines file, returning an empty list if it doesn't exist.
+    ///
+    /// Lines that can't be parsed (e.g. a record truncated by a full disk) are skipped.
+    pub(crate) fn read_jsonl<T>(&self, file_name: &str) -> Result<Vec<T>>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let path = self.path.join(file_name);
+        let reader = match self.dir.open(file_name) {
+            Ok(reader) => io::BufReader::new(reader),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err).wrap_err_with(|| format!("failed to open `{path}`")),
+        };
+
+        let mut records = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line.wrap_err_with(|| format!("failed to read `{path}`"))?;
+            match serde_json::from_str(&line) {
+                Ok(record) => records.push(record),
+                Err(err) => tracing::debug!("skipping {path}:{}: {err}", index + 1),
+            }
+        }
+        Ok(records)
+    }
+
+    /// Reads the last record that can be parsed from a JSON Lines file, without parsing the
+    /// others.
+    pub(crate) fn read_last_jsonl<T>(&self, file_name: &str) -> Result<Option<T>>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let path = self.path.join(file_name);
+        let contents = match self.dir.read_to_string(file_name) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).wrap_err_with(|| format!("failed to read `{path}`")),
+        };
+        Ok(contents
+            .lines()
+            .rev()
+            .find_map(|line| serde_json::from_str(line).ok()))
+    }
+}
+
+/// Returns the total size of the files within a directory, without following symlinks.
+///
+/// Files with several hard links within the directory (e.g. artifacts that Cargo uplifts from
+/// `deps/`) are only counted once.
+pub(crate) fn dir_size(path: &Utf8Path) -> Result<u64> {
+    add_dir_size(path, &mut HashSet::new())
+}
+
+fn add_dir_size(path: &Utf8Path, seen: &mut HashSet<(u64, u64)>) -> Result<u64> {
+    let metadata = match path.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => {
+            return Err(err).wrap_err_with(|| format!("failed to read metadata for `{path}`"))
+        }
+    };
+    if !metadata.is_dir() {
+        if metadata.nlink() > 1 && !seen.insert((metadata.dev(), metadata.ino())) {
+            return Ok(0);
+        }
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+    let entries = path
+        .read_dir_utf8()
+        .wrap_err_with(|| format!("failed to read directory `{path}`"))?;
+    for entry in entries {
+        let entry = entry.wrap_err_with(|| format!("failed to read entry in `{path}`"))?;
+        size += add_dir_size(entry.path(), seen)?;
+    }
+    Ok(size)
+}
+
+/// Returns true if the current user can create files in a directory.
+pub(crate) fn is_writable(dir: &Utf8Path) -> bool {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let Ok(dir_c) = CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: the path is a valid nul-terminated string.
+    unsafe { libc::access(dir_c.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// Formats a number of bytes in human-readable form, e.g. `1.5 GiB`.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Moves a directory, copying it if it's on a different filesystem.
+pub(crate) fn move_dir_all(src: &Utf8Path, dest: &Utf8Path) -> Result<()> {
+    match fs::rename(src, dest) {
+        Ok(()) => return Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {}
+        Err(err) => {
+            return Err(err).wrap_err_with(|| format!("failed to move `{src}` to `{dest}`"));
+        }
+    }
+
+    // Copy to a temporary directory first, so that an interrupted copy isn't mistaken for a
+    // complete one.
+    copy_dir_via_tmp(src, dest, |tmp_dest| {
+        fs::rename(tmp_dest, dest)
+            .wrap_err_with(|| format!("failed to rename `{tmp_dest}` to `{dest}`"))
+    })?;
+    fs::remove_dir_all(src).wrap_err_with(|| format!("failed to remove `{src}`"))?;
+    Ok(())
+}
+
+/// Copies `src` to a temporary sibling of `dest`, then calls `finish` to move it into place.
+///
+/// The temporary directory is removed if either step fails.
+fn copy_dir_via_tmp(
+    src: &Utf8Path,
+    dest: &Utf8Path,
+    finish: impl FnOnce(&Utf8Path) -> Result<()>,
+) -> Result<()> {
+    let tmp_dest = tmp_sibling(dest);
+    let result = copy_dir_all(src, &tmp_dest).and_then(|()| finish(&tmp_dest));
+    if result.is_err() {
+        _ = fs::remove_dir_all(&tmp_dest);
+    }
+    result
+}
+
+/// Copies a directory recursively, preserving symlinks.
+///
+/// Hard links aren't preserved, so artifacts shared with `targo share-deps` become separate
+/// copies again. This applies to moves across filesystems, and to volatile root snapshots and
+/// restores.
+fn copy_dir_all(src: &Utf8Path, dest: &Utf8Path) -> Result<()> {
+    fs::create_dir(dest).wrap_err_with(|| format!("failed to create directory `{dest}`"))?;
+    let entries = src
+        .read_dir_utf8()
+        .wrap_err_with(|| format!("failed to read directory `{src}`"))?;
+    for entry in entries {
+        let entry = entry.wrap_err_with(|| format!("failed to read entry in `{src}`"))?;
+        let file_type = entry
+            .file_type()
+            .wrap_err_with(|| format!("failed to read file type of `{}`", entry.path()))?;
+        let dest_path = dest.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir_all(entry.path(), &dest_path)?;
+        } else if file_type.is_symlink() {
+            let link = fs::read_link(entry.path())
+                .wrap_err_with(|| format!("failed to read symlink `{}`", entry.path()))?;
+            std::os::unix::fs::symlink(&link, &dest_path)
+                .wrap_err_with(|| format!("failed to create symlink `{dest_path}`"))?;
+        } else {
+            fs::copy(entry.path(), &dest_path)
+                .wrap_err_with(|| format!("failed to copy `{}` to `{dest_path}`", entry.path()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces `dest` with a copy of the directory at `src`.
+///
+/// The copy is made under a temporary name first, so `dest` is never partially written.
+pub(crate) fn replace_dir_with_copy(src: &Utf8Path, dest: &Utf8Path) -> Result<()> {
+    copy_dir_via_tmp(src, dest, |tmp_dest| {
+        match fs::remove_dir_all(dest) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("failed to remove `{dest}`"));
+            }
+        }
+        fs::rename(tmp_dest, dest)
+            .wrap_err_with(|| format!("failed to rename `{tmp_dest}` to `{dest}`"))
+    })
+}
+
+/// Atomically replaces the symlink at `link` with one pointing to `dest`.
+pub(crate) fn replace_symlink(link: &Utf8Path, dest: &Utf8Path) -> Result<()> {
+    let tmp_link = tmp_sibling(link);
+    std::os::unix::fs::symlink(dest, &tmp_link)
+        .wrap_err_with(|| format!("failed to create symlink from `{tmp_link}` to `{dest}`"))?;
+    fs::rename(&tmp_link, link)
+        .wrap_err_with(|| format!("failed to rename symlink `{tmp_link}` to `{link}`"))?;
+    Ok(())
+}
+
+/// Replaces a symlink with a directory, removing the symlink.
+///
+/// On Linux, the two are swapped atomically so that the path is never missing. Elsewhere, the
+/// symlink is removed just before the directory is renamed into place.
+pub(crate) fn replace_symlink_with_dir(link: &Utf8Path, dir: &Utf8Path) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let to_cstring = |path: &Utf8Path| {
+            CString::new(path.as_os_str().as_bytes())
+                .wrap_err_with(|| format!("path `{path}` contains a nul byte"))
+        };
+        let (dir_c, link_c) = (to_cstring(dir)?, to_cstring(link)?);
+        // SAFETY: both paths are valid nul-terminated strings.
+        let ret = unsafe {
+            libc::renameat2(
+                libc::AT_FDCWD,
+                dir_c.as_ptr(),
+                libc::AT_FDCWD,
+                link_c.as_ptr(),
+                libc::RENAME_EXCHANGE,
+            )
+        };
+        if ret == 0 {
+            // The symlink is now at the directory's old path.
+            return fs::remove_file(dir)
+                .wrap_err_with(|| format!("failed to remove old symlink at `{dir}`"));
+        }
+        let err = io::Error::last_os_error();
+        // Older kernels and some filesystems don't support RENAME_EXCHANGE.
+        if !matches!(err.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS)) {
+            return Err(err)
+                .wrap_err_with(|| format!("failed to exchange `{dir}` with symlink `{link}`"));
+        }
+    }
+
+    fs::remove_file(link).wrap_err_with(|| format!("failed to remove symlink `{link}`"))?;
+    fs::rename(dir, link).wrap_err_with(|| format!("failed to rename `{dir}` to `{link}`"))
+}
+
+/// Atomically replaces the file at `dest` with a hard link to `src`.
+pub(crate) fn replace_with_hard_link(src: &Utf8Path, dest: &Utf8Path) -> Result<()> {
+    let tmp_dest = tmp_sibling(dest);
+    fs::hard_link(src, &tmp_dest)
+        .wrap_err_with(|| format!("failed to link `{src}` to `{tmp_dest}`"))?;
+    fs::rename(&tmp_dest, dest).wrap_err_with(|| {
+        _ = fs::remove_file(&tmp_dest);
+        format!("failed to rename `{tmp_dest}` to `{dest}`")
+    })
+}
+
+/// Returns a temporary path in the same directory as `path`.
+fn tmp_sibling(path: &Utf8Path) -> Utf8PathBuf {
+    let file_name = path.file_name().unwrap_or("targo");
+    path.with_file_name(format!(".{file_name}.targo-tmp-{}", std::process::id()))
+}
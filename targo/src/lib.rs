@@ -3,6 +3,12 @@ mod config;
 mod dispatch;
 mod helpers;
 mod metadata;
+mod migrate;
+mod output;
+mod shared_deps;
+mod shell;
+mod shim;
+mod stats;
 mod store;
 
 pub use dispatch::*;
@@ -1,7 +1,12 @@
-use clap::Parser;
-
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
-    let app = targo::TargoApp::parse();
+    let mut args = std::env::args_os();
+    if args
+        .next()
+        .is_some_and(|argv0| targo::is_cargo_shim(&argv0))
+    {
+        return targo::exec_cargo_shim(args.collect());
+    }
+    let app = targo::TargoApp::parse_env();
     app.exec()
 }
@@ -1,15 +1,20 @@
 use camino::{Utf8Path, Utf8PathBuf};
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, TimeDelta};
 use color_eyre::{eyre::bail, Result};
+use schemars::JsonSchema;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct TargoStoreMetadata {
     store_version: u32,
     min_version: Version,
+    /// Workspaces that have been ejected with `targo eject`, and that targo should leave alone.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    #[schemars(with = "BTreeSet<String>")]
+    pub(crate) opted_out: BTreeSet<Utf8PathBuf>,
 }
 
 impl TargoStoreMetadata {
@@ -21,6 +26,7 @@ impl TargoStoreMetadata {
         Self {
             store_version: Self::STORE_VERSION,
             min_version: Self::MIN_VERSION,
+            opted_out: BTreeSet::new(),
         }
     }
 
@@ -48,10 +54,19 @@ impl TargoStoreMetadata {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct TargetDirMetadata {
+    /// Symlinks to this target directory.
+    #[schemars(with = "BTreeSet<String>")]
     pub(crate) backlinks: BTreeSet<Utf8PathBuf>,
+    /// Workspaces that use this target directory via `CARGO_TARGET_DIR` rather than a symlink.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    #[schemars(with = "BTreeSet<String>")]
+    pub(crate) env_workspaces: BTreeSet<Utf8PathBuf>,
+    /// What this target directory is used for, if it isn't a symlinked workspace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) used_for: Option<TargetDirUse>,
     pub(crate) last_used: DateTime<Local>,
 }
 
@@ -61,6 +76,8 @@ impl TargetDirMetadata {
     pub(crate) fn new() -> Self {
         Self {
             backlinks: BTreeSet::new(),
+            env_workspaces: BTreeSet::new(),
+            used_for: None,
             last_used: Local::now(),
         }
     }
@@ -69,3 +86,92 @@ impl TargetDirMetadata {
         self.last_used = Local::now();
     }
 }
+
+/// Uses for a managed target directory other than being symlinked to from a workspace.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub(crate) enum TargetDirUse {
+    /// Builds for `cargo install`.
+    Install(InstallSource),
+    /// Builds for a single-file Cargo script.
+    Script {
+        /// The absolute path to the script.
+        #[schemars(with = "String")]
+        path: Utf8PathBuf,
+    },
+}
+
+impl TargetDirUse {
+    /// Returns a key that uniquely identifies this use, used to name its target directory.
+    pub(crate) fn key(&self) -> String {
+        match self {
+            Self::Install(source) => source.key(),
+            Self::Script { path } => format!("script:{path}"),
+        }
+    }
+}
+
+/// The crates and source for a `cargo install` invocation.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct InstallSource {
+    /// Where the crates are installed from, e.g. `registry+crates-io` or `git+<url>`.
+    pub(crate) source: String,
+    /// The crates being installed, without version requirements.
+    pub(crate) crates: BTreeSet<String>,
+}
+
+impl InstallSource {
+    /// Returns a key that uniquely identifies this source, used to name its target directory.
+    pub(crate) fn key(&self) -> String {
+        let crates: Vec<_> = self.crates.iter().map(String::as_str).collect();
+        format!("install:{}:{}", self.source, crates.join(","))
+    }
+}
+
+/// A record of a single wrapped Cargo invocation, stored in a managed target directory's
+/// history log.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct HistoryRecord {
+    /// When Cargo was started.
+    pub(crate) timestamp: DateTime<Local>,
+    pub(crate) subcommand: Option<String>,
+    /// The arguments passed to Cargo, lossily converted to UTF-8.
+    pub(crate) args: Vec<String>,
+    /// The output of `rustc -V` for the toolchain in use, or the toolchain name if that failed.
+    pub(crate) toolchain: Option<String>,
+    /// Cargo's exit code, or `None` if it was terminated by a signal.
+    pub(crate) exit_code: Option<i32>,
+    /// The signal that terminated Cargo, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) signal: Option<i32>,
+    pub(crate) duration_ms: u64,
+    /// The size of the target directory in bytes before Cargo ran, as measured after the
+    /// previous recorded build.
+    pub(crate) size_before: u64,
+    /// The size of the target directory in bytes after Cargo exited. Subcommands that don't
+    /// write to the target directory, like `cargo metadata`, aren't measured.
+    pub(crate) size_after: u64,
+}
+
+impl HistoryRecord {
+    pub(crate) const HISTORY_FILE_NAME: &'static str = "history.jsonl";
+}
+
+/// The output of `rustc -V`, cached in a managed target directory so that it isn't run for
+/// every build.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ToolchainCache {
+    /// How the toolchain was selected, e.g. by a `+toolchain` override.
+    pub(crate) key: String,
+    pub(crate) version: String,
+    pub(crate) checked_at: DateTime<Local>,
+}
+
+impl ToolchainCache {
+    pub(crate) const FILE_NAME: &'static str = "toolchain-cache.json";
+    /// How long a cached version is used for, so that toolchain updates are picked up.
+    pub(crate) const MAX_AGE: TimeDelta = TimeDelta::hours(1);
+}
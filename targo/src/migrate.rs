@@ -0,0 +1,105 @@
+//! Finding workspaces to migrate with `targo migrate-tree`.
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::{eyre::Context, Result};
+use std::io;
+
+/// Finds directories under `root` that contain both a `Cargo.toml` and a `target` entry.
+///
+/// `target` directories, hidden directories (such as `.git`) and symlinks aren't descended into.
+/// Directories that can't be read are skipped.
+pub(crate) fn find_candidates(root: &Utf8Path) -> Result<Vec<Utf8PathBuf>> {
+    let mut candidates = Vec::new();
+    add_candidates(root, &mut candidates)?;
+    candidates.sort();
+    Ok(candidates)
+}
+
+fn add_candidates(dir: &Utf8Path, candidates: &mut Vec<Utf8PathBuf>) -> Result<()> {
+    let entries = match dir.read_dir_utf8() {
+        Ok(entries) => entries,
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+            ) =>
+        {
+            tracing::debug!("skipping `{dir}`: {err}");
+            return Ok(());
+        }
+        Err(err) => return Err(err).wrap_err_with(|| format!("failed to read directory `{dir}`")),
+    };
+
+    let mut has_manifest = false;
+    let mut has_target = false;
+    let mut subdirs = Vec::new();
+    for entry in entries {
+        let entry = entry.wrap_err_with(|| format!("failed to read entry in `{dir}`"))?;
+        let name = entry.file_name();
+        match name {
+            "Cargo.toml" => has_manifest = true,
+            "target" => has_target = true,
+            _ if name.starts_with('.') => {}
+            _ => {
+                let file_type = entry
+                    .file_type()
+                    .wrap_err_with(|| format!("failed to read file type for `{}`", entry.path()))?;
+                if file_type.is_dir() {
+                    subdirs.push(entry.into_path());
+                }
+            }
+        }
+    }
+
+    if has_manifest && has_target {
+        candidates.push(dir.to_owned());
+    }
+    for subdir in subdirs {
+        add_candidates(&subdir, candidates)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_find_candidates() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let root = Utf8Path::from_path(temp_dir.path()).expect("temp dir is UTF-8");
+
+        for dir in [
+            "a/target/debug",
+            "b/target",
+            "b/member/src",
+            "c/src",
+            "nested/d/target",
+            "nested/d/sub/target",
+            ".hidden/e/target",
+        ] {
+            fs::create_dir_all(root.join(dir))?;
+        }
+        for manifest in [
+            "a",
+            "b",
+            "b/member",
+            "c",
+            "nested/d",
+            "nested/d/sub",
+            ".hidden/e",
+            "a/target/debug",
+        ] {
+            fs::write(root.join(manifest).join("Cargo.toml"), "")?;
+        }
+
+        let candidates = find_candidates(root)?;
+        let expected: Vec<_> = ["a", "b", "nested/d", "nested/d/sub"]
+            .map(|dir| root.join(dir))
+            .into();
+        assert_eq!(candidates, expected, "candidates match");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,378 @@
+//! Machine-readable output for management commands (`--message-format json`).
+
+use crate::{
+    config::Placement,
+    metadata::{HistoryRecord, TargetDirMetadata},
+    stats::StoreStats,
+};
+use camino::Utf8PathBuf;
+use chrono::{DateTime, Local};
+use color_eyre::{eyre::Context, Result};
+use schemars::{JsonSchema, Schema};
+use serde::Serialize;
+
+/// The version of the JSON output schema.
+///
+/// Fields may be added without changing the version, but removing or changing the meaning of a
+/// field requires a bump.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+/// A JSON message printed to stdout, tagged with the schema version.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct JsonMessage<T> {
+    schema_version: u32,
+    #[serde(flatten)]
+    data: T,
+}
+
+/// Prints a message to stdout as a single line of JSON.
+pub(crate) fn print_json<T: Serialize>(data: T) -> Result<()> {
+    let message = JsonMessage {
+        schema_version: SCHEMA_VERSION,
+        data,
+    };
+    let json = serde_json::to_string(&message).wrap_err("failed to serialize JSON output")?;
+    println!("{json}");
+    Ok(())
+}
+
+/// Returns the JSON schema for the output of each command, keyed by command name.
+pub(crate) fn json_schemas() -> Vec<(&'static str, Schema)> {
+    vec![
+        ("adopt", schemars::schema_for!(JsonMessage<AdoptReport>)),
+        ("eject", schemars::schema_for!(JsonMessage<EjectReport>)),
+        ("gc", schemars::schema_for!(JsonMessage<GcReport>)),
+        ("history", schemars::schema_for!(JsonMessage<HistoryReport>)),
+        (
+            "migrate-tree",
+            schemars::schema_for!(JsonMessage<MigrateTreeReport>),
+        ),
+        (
+            "share-deps",
+            schemars::schema_for!(JsonMessage<ShareDepsReport>),
+        ),
+        ("stats", schemars::schema_for!(JsonMessage<StoreStats>)),
+        ("status", schemars::schema_for!(JsonMessage<StatusReport>)),
+        (
+            "store-move-dir",
+            schemars::schema_for!(JsonMessage<MoveDirReport>),
+        ),
+        ("store-sync", schemars::schema_for!(JsonMessage<SyncReport>)),
+    ]
+}
+
+/// The result of `targo adopt`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct AdoptReport {
+    /// If true, nothing was actually changed.
+    pub(crate) dry_run: bool,
+    pub(crate) workspaces: Vec<AdoptedWorkspace>,
+}
+
+/// A workspace handled by `targo adopt`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct AdoptedWorkspace {
+    #[schemars(with = "String")]
+    pub(crate) workspace: Utf8PathBuf,
+    #[schemars(with = "String")]
+    pub(crate) target_dir: Utf8PathBuf,
+    /// What was (or would be) done for the workspace.
+    pub(crate) action: StatusAction,
+    /// The managed `target` directory in the store, if one was set up.
+    #[schemars(with = "Option<String>")]
+    pub(crate) managed_path: Option<Utf8PathBuf>,
+}
+
+/// The result of `targo migrate-tree`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct MigrateTreeReport {
+    /// If true, nothing was actually moved.
+    pub(crate) dry_run: bool,
+    pub(crate) migrated: Vec<MigratedWorkspace>,
+    pub(crate) skipped: Vec<SkippedWorkspace>,
+    /// The total size of the moved directories, in bytes.
+    pub(crate) total_size: u64,
+}
+
+/// A workspace whose `target` directory was moved into the store by `targo migrate-tree`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct MigratedWorkspace {
+    #[schemars(with = "String")]
+    pub(crate) workspace: Utf8PathBuf,
+    /// The managed `target` directory in the store, or `None` for a dry run.
+    #[schemars(with = "Option<String>")]
+    pub(crate) managed_path: Option<Utf8PathBuf>,
+    /// The size of the moved directory, in bytes.
+    pub(crate) size: u64,
+}
+
+/// A workspace left alone by `targo migrate-tree`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct SkippedWorkspace {
+    #[schemars(with = "String")]
+    pub(crate) workspace: Utf8PathBuf,
+    pub(crate) reason: SkipReason,
+}
+
+/// Why `targo migrate-tree` left a workspace alone.
+#[derive(Clone, Copy, Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum SkipReason {
+    /// The workspace is excluded by config.
+    Excluded,
+    /// The workspace was ejected.
+    OptedOut,
+    /// The workspace uses `placement = "env"`, so its `target` directory isn't replaced.
+    EnvPlacement,
+    /// The `target` path is already a symlink to the store.
+    AlreadyManaged,
+    /// The `target` path is something targo doesn't manage, such as a symlink elsewhere.
+    Foreign,
+    /// The workspace can't be written to.
+    NotWritable,
+}
+
+impl SkipReason {
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            Self::Excluded => "excluded by config",
+            Self::OptedOut => "ejected",
+            Self::EnvPlacement => "uses env placement",
+            Self::AlreadyManaged => "already managed",
+            Self::Foreign => "target path isn't managed by targo",
+            Self::NotWritable => "not writable",
+        }
+    }
+}
+
+/// The result of `targo eject`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct EjectReport {
+    #[schemars(with = "String")]
+    pub(crate) workspace: Utf8PathBuf,
+    #[schemars(with = "String")]
+    pub(crate) target_dir: Utf8PathBuf,
+    /// The managed directory that was moved back into the workspace, or `None` if the
+    /// workspace didn't have a managed symlink.
+    #[schemars(with = "Option<String>")]
+    pub(crate) moved_from: Option<Utf8PathBuf>,
+    /// The size of the moved directory, in bytes.
+    pub(crate) size: u64,
+}
+
+/// The result of `targo gc`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct GcReport {
+    /// If true, nothing was actually removed.
+    pub(crate) dry_run: bool,
+    pub(crate) removed: Vec<RemovedDir>,
+    /// The total size of the removed directories, in bytes.
+    pub(crate) total_size: u64,
+    /// The size of shared dependency artifacts removed because no managed directory uses them
+    /// anymore, in bytes.
+    pub(crate) shared_deps_size: u64,
+}
+
+/// A managed target directory removed by `targo gc`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct RemovedDir {
+    #[schemars(with = "String")]
+    pub(crate) path: Utf8PathBuf,
+    pub(crate) root: String,
+    pub(crate) size: u64,
+    pub(crate) metadata: TargetDirMetadata,
+}
+
+/// The result of `targo share-deps`.
+#[derive(Debug, Default, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ShareDepsReport {
+    /// If true, nothing was actually changed.
+    pub(crate) dry_run: bool,
+    /// The number of registry dependency artifacts found.
+    pub(crate) scanned: usize,
+    /// Artifacts that were already linked to the shared directory.
+    pub(crate) already_shared: usize,
+    /// Artifacts that were added to the shared directory.
+    pub(crate) stored: usize,
+    /// Artifacts that were replaced with a link to an identical shared artifact.
+    pub(crate) linked: usize,
+    /// The disk space saved by linking, in bytes.
+    pub(crate) saved_size: u64,
+}
+
+/// The result of `targo store move-dir`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct MoveDirReport {
+    #[schemars(with = "String")]
+    pub(crate) workspace: Utf8PathBuf,
+    pub(crate) from_root: String,
+    pub(crate) to_root: String,
+    /// The new location of the managed directory.
+    #[schemars(with = "String")]
+    pub(crate) path: Utf8PathBuf,
+}
+
+/// The result of `targo store sync`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct SyncReport {
+    #[schemars(with = "String")]
+    pub(crate) workspace: Utf8PathBuf,
+    #[schemars(with = "String")]
+    pub(crate) source: Utf8PathBuf,
+    #[schemars(with = "String")]
+    pub(crate) snapshot: Utf8PathBuf,
+    /// The size of the snapshot, in bytes.
+    pub(crate) size: u64,
+}
+
+/// The result of `targo history`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct HistoryReport {
+    #[schemars(with = "String")]
+    pub(crate) workspace: Utf8PathBuf,
+    /// Recorded builds, oldest first.
+    pub(crate) records: Vec<HistoryRecord>,
+}
+
+/// The result of `targo status`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct StatusReport {
+    /// The workspace root, or `None` if the directory isn't within a Cargo workspace.
+    #[schemars(with = "Option<String>")]
+    pub(crate) workspace: Option<Utf8PathBuf>,
+    /// The workspace's `target` path.
+    #[schemars(with = "Option<String>")]
+    pub(crate) target_dir: Option<Utf8PathBuf>,
+    pub(crate) kind: Option<TargetDirKindName>,
+    pub(crate) placement: Placement,
+    /// Whether the workspace is excluded by config.
+    pub(crate) excluded: bool,
+    /// Whether the workspace was ejected with `targo eject`.
+    pub(crate) opted_out: bool,
+    /// What `targo wrap-cargo` would do for this workspace.
+    pub(crate) action: StatusAction,
+    /// The managed directory for this workspace, if there is one.
+    pub(crate) managed: Option<ManagedDirStatus>,
+}
+
+/// What's at a workspace's `target` path.
+#[derive(Clone, Copy, Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum TargetDirKindName {
+    /// Nothing is there.
+    DoesNotExist,
+    /// A plain directory.
+    Directory,
+    /// A symlink to a managed directory in the store.
+    TargoSymlink,
+    /// Something targo doesn't manage, such as a symlink elsewhere.
+    Foreign,
+}
+
+/// What `targo wrap-cargo` would do for a workspace.
+#[derive(Clone, Copy, Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum StatusAction {
+    /// Nothing, since the directory isn't within a Cargo workspace.
+    NotInWorkspace,
+    /// Nothing, since the workspace is excluded by config.
+    Excluded,
+    /// Nothing, since the workspace was ejected.
+    OptedOut,
+    /// Create a symlink to a new managed directory.
+    CreateSymlink,
+    /// Replace the existing `target` directory with a symlink to a new managed directory. The
+    /// old directory's contents are discarded.
+    ReplaceDirectory,
+    /// Use the managed directory the existing symlink points to.
+    UseSymlink,
+    /// Nothing, since the `target` path isn't managed by targo.
+    LeaveAlone,
+    /// Point Cargo at the managed directory with `CARGO_TARGET_DIR`.
+    SetTargetDirEnv,
+    /// Point Cargo at the managed directory with `CARGO_TARGET_DIR`, since a symlink can't be
+    /// created in the workspace.
+    NotWritable,
+    /// Nothing, since the target directory is set explicitly through `CARGO_TARGET_DIR`.
+    ExplicitTargetDir,
+}
+
+impl StatusAction {
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            Self::NotInWorkspace => "none (not within a Cargo workspace)",
+            Self::Excluded => "none (workspace is excluded by config)",
+            Self::OptedOut => "none (workspace was ejected)",
+            Self::CreateSymlink => "create a symlink to a new managed directory",
+            Self::ReplaceDirectory => {
+                "replace the target directory with a symlink to a new managed directory"
+            }
+            Self::UseSymlink => "use the existing managed directory",
+            Self::LeaveAlone => "none (target path isn't managed by targo)",
+            Self::SetTargetDirEnv => "set CARGO_TARGET_DIR to the managed directory",
+            Self::NotWritable => {
+                "set CARGO_TARGET_DIR to the managed directory (workspace isn't writable)"
+            }
+            Self::ExplicitTargetDir => "none (CARGO_TARGET_DIR is set)",
+        }
+    }
+}
+
+/// A managed directory, as reported by `targo status`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ManagedDirStatus {
+    #[schemars(with = "String")]
+    pub(crate) path: Utf8PathBuf,
+    pub(crate) root: String,
+    /// The size of the managed `target` directory, in bytes.
+    pub(crate) size: u64,
+    pub(crate) last_used: Option<DateTime<Local>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camino::Utf8Path;
+
+    /// Checks that the schemas in `schemas/` are up to date. Run with `TARGO_UPDATE_SCHEMAS=1`
+    /// to regenerate them.
+    #[test]
+    fn test_json_schemas_up_to_date() -> Result<()> {
+        let schemas_dir = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("schemas");
+        let update = std::env::var_os("TARGO_UPDATE_SCHEMAS").is_some();
+
+        for (command, schema) in json_schemas() {
+            let path = schemas_dir.join(format!("{command}.json"));
+            let mut expected = serde_json::to_string_pretty(&schema)?;
+            expected.push('\n');
+            if update {
+                std::fs::create_dir_all(&schemas_dir)?;
+                std::fs::write(&path, &expected)?;
+                continue;
+            }
+            let actual = std::fs::read_to_string(&path)
+                .wrap_err_with(|| format!("failed to read `{path}`"))?;
+            assert_eq!(
+                actual, expected,
+                "`{path}` is out of date, run tests with TARGO_UPDATE_SCHEMAS=1 to update it"
+            );
+        }
+
+        Ok(())
+    }
+}
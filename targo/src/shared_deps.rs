@@ -0,0 +1,262 @@
+//! Sharing identical dependency artifacts between managed target directories.
+//!
+//! Cargo names library artifacts in `deps/` with a metadata hash, e.g.
+//! `libserde-0123456789abcdef.rlib`, which covers the crate's version, source, features, profile
+//! and toolchain. Cargo's fingerprint for the unit, in `.fingerprint/serde-0123456789abcdef/`,
+//! records that the artifact was built successfully. Artifacts of registry crates are stored once
+//! in a content-addressed directory in each store root, and hard-linked into every managed
+//! directory that has them.
+
+use crate::stats::is_profile_dir;
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::{eyre::Context, Result};
+use std::{collections::HashSet, fs, io, io::Read, os::unix::fs::MetadataExt};
+use xxhash_rust::xxh3::Xxh3;
+
+/// The content-addressed directory within each store root.
+pub(crate) const SHARED_DIR_NAME: &str = ".targo-shared-deps";
+
+const ARTIFACT_EXTENSIONS: [&str; 5] = ["rlib", "rmeta", "so", "dylib", "a"];
+
+/// Returns the `<name>-<hash>` unit name for a library artifact in `deps/`, or `None` if the file
+/// isn't one.
+pub(crate) fn unit_name(file_name: &str) -> Option<&str> {
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    if !ARTIFACT_EXTENSIONS.contains(&extension) {
+        return None;
+    }
+    let unit = stem.strip_prefix("lib")?;
+    let (_, hash) = unit.rsplit_once('-')?;
+    (hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit())).then_some(unit)
+}
+
+/// Finds artifacts of registry crates in a `target` directory, across all profiles and target
+/// triples.
+pub(crate) fn find_artifacts(
+    target_dir: &Utf8Path,
+    registry_src: &Utf8Path,
+) -> Result<Vec<Utf8PathBuf>> {
+    let mut artifacts = Vec::new();
+    let Ok(entries) = target_dir.read_dir_utf8() else {
+        return Ok(artifacts);
+    };
+    for entry in entries {
+        let entry = entry.wrap_err_with(|| format!("failed to read entry in `{target_dir}`"))?;
+        let path = entry.path();
+        if is_profile_dir(path) {
+            add_artifacts(&path.join("deps"), registry_src, &mut artifacts)?;
+        } else if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            // A `--target <triple>` directory.
+            let sub_entries = path
+                .read_dir_utf8()
+                .wrap_err_with(|| format!("failed to read directory `{path}`"))?;
+            for sub_entry in sub_entries {
+                let sub_entry =
+                    sub_entry.wrap_err_with(|| format!("failed to read entry in `{path}`"))?;
+                if is_profile_dir(sub_entry.path()) {
+                    add_artifacts(&sub_entry.path().join("deps"), registry_src, &mut artifacts)?;
+                }
+            }
+        }
+    }
+    Ok(artifacts)
+}
+
+fn add_artifacts(
+    deps_dir: &Utf8Path,
+    registry_src: &Utf8Path,
+    artifacts: &mut Vec<Utf8PathBuf>,
+) -> Result<()> {
+    let Ok(entries) = deps_dir.read_dir_utf8() else {
+        return Ok(());
+    };
+    for entry in entries {
+        let entry = entry.wrap_err_with(|| format!("failed to read entry in `{deps_dir}`"))?;
+        let Some(unit) = unit_name(entry.file_name()) else {
+            continue;
+        };
+        let is_file = entry
+            .file_type()
+            .wrap_err_with(|| format!("failed to read file type for `{}`", entry.path()))?
+            .is_file();
+        if is_file
+            && is_fresh_unit(deps_dir, unit)
+            && is_registry_unit(deps_dir, unit, registry_src)
+        {
+            artifacts.push(entry.into_path());
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if Cargo's fingerprint for a unit is present, meaning that its artifacts were
+/// built successfully.
+///
+/// Cargo removes the fingerprint before rebuilding a unit, so artifacts from failed or
+/// interrupted builds aren't shared.
+fn is_fresh_unit(deps_dir: &Utf8Path, unit: &str) -> bool {
+    let Some(profile_dir) = deps_dir.parent() else {
+        return false;
+    };
+    let (name, _) = unit.rsplit_once('-').expect("unit names have a hash");
+    let hash_file = profile_dir
+        .join(".fingerprint")
+        .join(unit)
+        .join(format!("lib-{name}"));
+    fs::read_to_string(hash_file).is_ok_and(|hash| {
+        let hash = hash.trim();
+        !hash.is_empty() && hash.bytes().all(|b| b.is_ascii_hexdigit())
+    })
+}
+
+/// Returns the device and inode numbers of every artifact in a shared directory.
+///
+/// Artifacts that are hard links to one of these are already shared, and don't need to be hashed.
+pub(crate) fn shared_inodes(shared_dir: &Utf8Path) -> Result<HashSet<(u64, u64)>> {
+    let mut inodes = HashSet::new();
+    let prefixes = match shared_dir.read_dir_utf8() {
+        Ok(prefixes) => prefixes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(inodes),
+        Err(err) => return Err(err).wrap_err_with(|| format!("failed to read `{shared_dir}`")),
+    };
+    for prefix in prefixes {
+        let prefix = prefix.wrap_err_with(|| format!("failed to read `{shared_dir}`"))?;
+        let entries = prefix
+            .path()
+            .read_dir_utf8()
+            .wrap_err_with(|| format!("failed to read `{}`", prefix.path()))?;
+        for entry in entries {
+            let entry = entry.wrap_err_with(|| format!("failed to read `{}`", prefix.path()))?;
+            let metadata = entry
+                .metadata()
+                .wrap_err_with(|| format!("failed to read metadata for `{}`", entry.path()))?;
+            inodes.insert((metadata.dev(), metadata.ino()));
+        }
+    }
+    Ok(inodes)
+}
+
+/// Returns true if the dep-info file for a unit shows that it was built only from registry
+/// sources.
+///
+/// Units that also read generated sources (e.g. from a build script's `OUT_DIR`) aren't shared.
+fn is_registry_unit(deps_dir: &Utf8Path, unit: &str, registry_src: &Utf8Path) -> bool {
+    let Ok(dep_info) = fs::read_to_string(deps_dir.join(format!("{unit}.d"))) else {
+        return false;
+    };
+    // The first line is `<outputs>: <sources>`.
+    let Some((_, sources)) = dep_info
+        .lines()
+        .next()
+        .and_then(|line| line.split_once(": "))
+    else {
+        return false;
+    };
+    let mut sources = sources.split_whitespace().peekable();
+    sources.peek().is_some()
+        && sources.all(|source| Utf8Path::new(source).starts_with(registry_src))
+}
+
+/// Returns the content hash of a file, as used for its name in the shared directory.
+pub(crate) fn content_hash(path: &Utf8Path) -> Result<String> {
+    let mut file = fs::File::open(path).wrap_err_with(|| format!("failed to open `{path}`"))?;
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .wrap_err_with(|| format!("failed to read `{path}`"))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:032x}", hasher.digest128()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_name() {
+        let cases = [
+            (
+                "libserde-0123456789abcdef.rlib",
+                Some("serde-0123456789abcdef"),
+            ),
+            (
+                "libserde-0123456789abcdef.rmeta",
+                Some("serde-0123456789abcdef"),
+            ),
+            (
+                "libserde_derive-0123456789abcdef.so",
+                Some("serde_derive-0123456789abcdef"),
+            ),
+            ("serde-0123456789abcdef.d", None),
+            ("libserde-0123456789abcdef.rlib.tmp", None),
+            ("libserde.rlib", None),
+            ("libserde-0123.rlib", None),
+            ("libserde-0123456789abcdeg.rlib", None),
+            ("foo-0123456789abcdef", None),
+        ];
+        for (file_name, expected) in cases {
+            assert_eq!(unit_name(file_name), expected, "{file_name}");
+        }
+    }
+
+    #[test]
+    fn test_find_artifacts() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = Utf8Path::from_path(temp_dir.path()).expect("temp dir is UTF-8");
+        let registry_src = dir.join("registry/src");
+        let target_dir = dir.join("target");
+
+        let registry_lib = registry_src.join("index/serde-1.0.0/src/lib.rs");
+        let local_lib = dir.join("workspace/src/lib.rs");
+        let units = [
+            ("debug", "serde-0123456789abcdef", &registry_lib),
+            ("debug", "local-0123456789abcdef", &local_lib),
+            (
+                "wasm32-unknown-unknown/release",
+                "serde-fedcba9876543210",
+                &registry_lib,
+            ),
+            ("release", "serde-00000000ffffffff", &registry_lib),
+        ];
+        for (profile, unit, source) in units {
+            let profile_dir = target_dir.join(profile);
+            fs::create_dir_all(profile_dir.join(".fingerprint"))?;
+            fs::create_dir_all(profile_dir.join("deps"))?;
+            // The release build of serde-00000000ffffffff didn't finish.
+            if unit != "serde-00000000ffffffff" {
+                let fingerprint_dir = profile_dir.join(format!(".fingerprint/{unit}"));
+                fs::create_dir_all(&fingerprint_dir)?;
+                let (name, _) = unit.rsplit_once('-').unwrap();
+                fs::write(
+                    fingerprint_dir.join(format!("lib-{name}")),
+                    "0123456789abcdef",
+                )?;
+            }
+            let artifact = profile_dir.join(format!("deps/lib{unit}.rlib"));
+            fs::write(&artifact, unit)?;
+            fs::write(
+                profile_dir.join(format!("deps/{unit}.d")),
+                format!("{artifact}: {source}\n\n{source}:\n"),
+            )?;
+        }
+
+        let mut artifacts = find_artifacts(&target_dir, &registry_src)?;
+        artifacts.sort();
+        let expected = vec![
+            target_dir.join("debug/deps/libserde-0123456789abcdef.rlib"),
+            target_dir.join("wasm32-unknown-unknown/release/deps/libserde-fedcba9876543210.rlib"),
+        ];
+        assert_eq!(
+            artifacts, expected,
+            "only successfully built registry artifacts are found"
+        );
+
+        Ok(())
+    }
+}
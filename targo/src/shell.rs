@@ -0,0 +1,35 @@
+//! Shell integration printed by `targo init`.
+
+use crate::dispatch::InitShell;
+
+const POSIX_INIT: &str = r#"# targo shell integration, from `targo init`.
+
+# Run Cargo through targo.
+cargo() {
+    targo wrap-cargo "$@"
+}
+
+# Run Cargo directly, bypassing targo.
+rawcargo() {
+    command cargo "$@"
+}
+"#;
+
+const FISH_INIT: &str = r#"# targo shell integration, from `targo init fish`.
+
+function cargo --wraps cargo --description 'Run Cargo through targo'
+    targo wrap-cargo $argv
+end
+
+function rawcargo --wraps cargo --description 'Run Cargo directly, bypassing targo'
+    command cargo $argv
+end
+"#;
+
+/// Returns the integration script for a shell.
+pub(crate) fn init_script(shell: InitShell) -> &'static str {
+    match shell {
+        InitShell::Bash | InitShell::Zsh => POSIX_INIT,
+        InitShell::Fish => FISH_INIT,
+    }
+}
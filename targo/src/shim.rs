@@ -0,0 +1,187 @@
+//! Running targo as `cargo`, by installing it under that name earlier in `PATH`.
+//!
+//! In this mode, the real Cargo is found by searching `PATH` for the next `cargo` that isn't
+//! targo itself.
+
+use color_eyre::{
+    eyre::{bail, Context},
+    Result,
+};
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+/// Set on every Cargo command run by the shim, to the path of the binary chosen as the real
+/// Cargo. If the shim finds that it was itself chosen, it bails out rather than recursing.
+pub(crate) const SHIM_CARGO_ENV: &str = "TARGO_SHIM_CARGO";
+
+/// The binary used to run Cargo.
+#[derive(Clone, Debug)]
+pub(crate) struct CargoBin {
+    pub(crate) path: OsString,
+    /// The `argv[0]` to run `path` with, if it differs from `path`.
+    pub(crate) arg0: Option<OsString>,
+    /// Whether this was chosen by the shim, in which case [`SHIM_CARGO_ENV`] is set for it.
+    pub(crate) from_shim: bool,
+}
+
+impl CargoBin {
+    /// Returns the Cargo binary to use outside of shim mode.
+    ///
+    /// `CARGO` is honored if it's set, and refused if it points at targo itself, since running
+    /// it would recurse. Otherwise, `PATH` is searched the same way as in shim mode, so that a
+    /// shim installed as `cargo` is never picked up.
+    pub(crate) fn from_env() -> Result<Self> {
+        let self_exe = std::env::current_exe().ok();
+        if let Some(cargo) = std::env::var_os("CARGO") {
+            if self_exe
+                .as_deref()
+                .is_some_and(|self_exe| same_file(Path::new(&cargo), self_exe))
+            {
+                bail!(
+                    "CARGO is set to `{}`, which is targo itself; \
+                     set it to the real cargo or unset it",
+                    Path::new(&cargo).display()
+                );
+            }
+            return Ok(Self::plain(cargo));
+        }
+        Ok(self_exe
+            .and_then(|self_exe| find_in_path(&self_exe))
+            .unwrap_or_else(|| Self::plain("cargo".into())))
+    }
+
+    /// Returns the real Cargo to use in shim mode.
+    ///
+    /// `CARGO` is ignored here: Cargo sets it for build scripts and subcommands to the
+    /// toolchain-specific binary, which doesn't understand `+toolchain` arguments.
+    pub(crate) fn for_shim() -> Result<Self> {
+        let self_exe = std::env::current_exe().wrap_err("failed to determine the path to targo")?;
+        if let Some(chosen) = std::env::var_os(SHIM_CARGO_ENV) {
+            if same_file(Path::new(&chosen), &self_exe) {
+                bail!(
+                    "`{}` was run as the real cargo, but it's targo itself \
+                     (is another copy of targo installed as `cargo` in PATH?)",
+                    Path::new(&chosen).display()
+                );
+            }
+        }
+        match find_in_path(&self_exe) {
+            Some(bin) => Ok(Self {
+                from_shim: true,
+                ..bin
+            }),
+            None => bail!(
+                "couldn't find cargo in PATH (other than targo at `{}`)",
+                self_exe.display()
+            ),
+        }
+    }
+
+    pub(crate) fn plain(path: OsString) -> Self {
+        Self {
+            path,
+            arg0: None,
+            from_shim: false,
+        }
+    }
+}
+
+/// Returns true if `argv0` names a `cargo` binary.
+pub(crate) fn invoked_as_cargo(argv0: &OsStr) -> bool {
+    Path::new(argv0).file_stem() == Some(OsStr::new("cargo"))
+}
+
+/// If targo was run as the `cargo targo` external subcommand, removes the `targo` argument that
+/// Cargo inserts after `argv[0]`. Returns true if it was.
+pub(crate) fn strip_cargo_subcommand(args: &mut Vec<OsString>) -> bool {
+    let is_subcommand = args
+        .first()
+        .is_some_and(|argv0| Path::new(argv0).file_stem() == Some(OsStr::new("cargo-targo")))
+        && args.get(1).is_some_and(|arg| arg == "targo");
+    if is_subcommand {
+        args.remove(1);
+    }
+    is_subcommand
+}
+
+/// Searches `PATH` for a `cargo` that isn't `self_exe`.
+///
+/// Symlinks and hard links to targo are recognized, so a shim installed with `ln -s` is
+/// skipped. A rustup proxy is used as is, so that `+toolchain` and `rust-toolchain.toml` keep
+/// working. If the shim has replaced the only `cargo` proxy, `rustup` itself is run as a proxy
+/// instead, since rustup picks the tool to run from `argv[0]`.
+fn find_in_path(self_exe: &Path) -> Option<CargoBin> {
+    let path = std::env::var_os("PATH")?;
+    let dirs: Vec<PathBuf> = std::env::split_paths(&path).collect();
+
+    for dir in &dirs {
+        let candidate = dir.join("cargo");
+        if !is_executable(&candidate) {
+            continue;
+        }
+        if same_file(&candidate, self_exe) {
+            tracing::debug!("skipping `{}`, which is targo", candidate.display());
+            continue;
+        }
+        return Some(CargoBin::plain(candidate.into()));
+    }
+
+    let rustup = dirs
+        .iter()
+        .map(|dir| dir.join("rustup"))
+        .find(|candidate| is_executable(candidate) && !same_file(candidate, self_exe))?;
+    tracing::debug!("running `{}` as a cargo proxy", rustup.display());
+    Some(CargoBin {
+        path: rustup.into(),
+        arg0: Some("cargo".into()),
+        from_shim: false,
+    })
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Returns true if both paths resolve to the same file.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invoked_as_cargo() {
+        assert!(invoked_as_cargo(OsStr::new("cargo")));
+        assert!(invoked_as_cargo(OsStr::new("/home/me/bin/cargo")));
+        assert!(!invoked_as_cargo(OsStr::new("targo")));
+        assert!(!invoked_as_cargo(OsStr::new("/usr/bin/cargo-clippy")));
+    }
+
+    #[test]
+    fn test_strip_cargo_subcommand() {
+        let cases: [(&[&str], &[&str], bool); 4] = [
+            (
+                &["/bin/cargo-targo", "targo", "gc"],
+                &["/bin/cargo-targo", "gc"],
+                true,
+            ),
+            (&["cargo-targo", "gc"], &["cargo-targo", "gc"], false),
+            (&["targo", "targo"], &["targo", "targo"], false),
+            (&["cargo-targo"], &["cargo-targo"], false),
+        ];
+        for (input, expected, stripped) in cases {
+            let mut args: Vec<OsString> = input.iter().map(OsString::from).collect();
+            assert_eq!(strip_cargo_subcommand(&mut args), stripped, "{input:?}");
+            assert_eq!(args, expected, "{input:?}");
+        }
+    }
+}
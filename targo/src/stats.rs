@@ -0,0 +1,254 @@
+use crate::{
+    helpers::{dir_size, format_bytes},
+    metadata::{TargetDirUse, TargoStoreMetadata},
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, Local};
+use color_eyre::{eyre::Context, Result};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::{cmp::Reverse, collections::BTreeMap, io};
+
+/// A summary of the store, as reported by `targo stats`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct StoreStats {
+    /// The store's metadata, or `None` if the store hasn't been used yet.
+    pub(crate) store_metadata: Option<TargoStoreMetadata>,
+    pub(crate) roots: Vec<RootStats>,
+    pub(crate) dir_count: usize,
+    pub(crate) total_size: u64,
+    /// The largest managed directories, largest first.
+    pub(crate) largest: Vec<DirStats>,
+    /// The most recently used managed directories, most recent first.
+    pub(crate) most_recently_used: Vec<DirStats>,
+    /// The threshold for [`Self::unused`].
+    pub(crate) unused_days: u32,
+    /// Managed directories that haven't been used in [`Self::unused_days`] days, least recently
+    /// used first.
+    pub(crate) unused: Vec<DirStats>,
+    /// The total size of each profile (e.g. `debug`, `release`, `doc`, or
+    /// `x86_64-unknown-linux-gnu/debug`) across all managed directories.
+    pub(crate) profiles: BTreeMap<String, u64>,
+}
+
+/// Statistics about a single root.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct RootStats {
+    pub(crate) name: String,
+    #[schemars(with = "String")]
+    pub(crate) path: Utf8PathBuf,
+    pub(crate) volatile: bool,
+    pub(crate) dir_count: usize,
+    pub(crate) size: u64,
+    /// The total size of the filesystem the root is on.
+    pub(crate) filesystem_total: u64,
+    /// The space available on the filesystem the root is on.
+    pub(crate) filesystem_free: u64,
+}
+
+/// Statistics about a single managed directory.
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct DirStats {
+    #[schemars(with = "String")]
+    pub(crate) path: Utf8PathBuf,
+    pub(crate) root: String,
+    /// Workspaces that use this directory, through a symlink or `CARGO_TARGET_DIR`.
+    #[schemars(with = "Vec<String>")]
+    pub(crate) workspaces: Vec<Utf8PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) used_for: Option<TargetDirUse>,
+    pub(crate) size: u64,
+    pub(crate) last_used: DateTime<Local>,
+}
+
+impl DirStats {
+    /// A short description of what the directory is used by.
+    fn label(&self) -> String {
+        if !self.workspaces.is_empty() {
+            let workspaces: Vec<_> = self.workspaces.iter().map(|ws| ws.as_str()).collect();
+            return workspaces.join(", ");
+        }
+        match &self.used_for {
+            Some(TargetDirUse::Install(source)) => format!("cargo install ({})", source.key()),
+            Some(TargetDirUse::Script { path }) => format!("script {path}"),
+            None => self.path.to_string(),
+        }
+    }
+}
+
+impl StoreStats {
+    /// Prints a human-readable report to stdout.
+    pub(crate) fn print_human(&self) {
+        println!("roots:");
+        for root in &self.roots {
+            println!(
+                "  {}{}  `{}`  {} dirs, {}  (filesystem: {} free of {})",
+                root.name,
+                if root.volatile { " (volatile)" } else { "" },
+                root.path,
+                root.dir_count,
+                format_bytes(root.size),
+                format_bytes(root.filesystem_free),
+                format_bytes(root.filesystem_total),
+            );
+        }
+        println!(
+            "managed dirs: {}, {} total",
+            self.dir_count,
+            format_bytes(self.total_size)
+        );
+
+        print_dirs("largest:", &self.largest);
+        print_dirs("most recently used:", &self.most_recently_used);
+        let unused_size: u64 = self.unused.iter().map(|dir| dir.size).sum();
+        print_dirs(
+            &format!(
+                "unused for {}+ days ({} dirs, {}):",
+                self.unused_days,
+                self.unused.len(),
+                format_bytes(unused_size)
+            ),
+            &self.unused,
+        );
+
+        if !self.profiles.is_empty() {
+            println!("\nby profile:");
+            let mut profiles: Vec<_> = self.profiles.iter().collect();
+            profiles.sort_by_key(|(_, size)| Reverse(**size));
+            for (profile, size) in profiles {
+                println!("  {:>10}  {profile}", format_bytes(*size));
+            }
+        }
+    }
+}
+
+fn print_dirs(heading: &str, dirs: &[DirStats]) {
+    println!("\n{heading}");
+    for dir in dirs {
+        println!(
+            "  {:>10}  {}  {}",
+            format_bytes(dir.size),
+            dir.last_used.format("%Y-%m-%d"),
+            dir.label()
+        );
+    }
+}
+
+/// Breaks down the size of a `target` directory by profile.
+///
+/// Profile directories are recognized by Cargo's `.fingerprint` directory, so custom profiles
+/// are found too. Directories for `--target <triple>` builds contain profile directories, which
+/// are reported as `<triple>/<profile>`. Everything else is reported as `other`.
+pub(crate) fn profile_sizes(target_dir: &Utf8Path) -> Result<BTreeMap<String, u64>> {
+    let mut sizes = BTreeMap::new();
+    add_profile_sizes(target_dir, None, &mut sizes)?;
+    Ok(sizes)
+}
+
+fn add_profile_sizes(
+    dir: &Utf8Path,
+    triple: Option<&str>,
+    sizes: &mut BTreeMap<String, u64>,
+) -> Result<()> {
+    let entries = match dir.read_dir_utf8() {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).wrap_err_with(|| format!("failed to read directory `{dir}`")),
+    };
+
+    for entry in entries {
+        let entry = entry.wrap_err_with(|| format!("failed to read entry in `{dir}`"))?;
+        let path = entry.path();
+        let name = entry.file_name();
+        let is_dir = entry
+            .file_type()
+            .wrap_err_with(|| format!("failed to read file type for `{path}`"))?
+            .is_dir();
+
+        let key = if is_dir && (name == "doc" || is_profile_dir(path)) {
+            match triple {
+                Some(triple) => format!("{triple}/{name}"),
+                None => name.to_owned(),
+            }
+        } else if is_dir && triple.is_none() && is_triple_dir(path)? {
+            add_profile_sizes(path, Some(name), sizes)?;
+            continue;
+        } else {
+            "other".to_owned()
+        };
+        *sizes.entry(key).or_default() += dir_size(path)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn is_profile_dir(path: &Utf8Path) -> bool {
+    path.join(".fingerprint").is_dir()
+}
+
+fn is_triple_dir(path: &Utf8Path) -> Result<bool> {
+    let entries = path
+        .read_dir_utf8()
+        .wrap_err_with(|| format!("failed to read directory `{path}`"))?;
+    for entry in entries {
+        let entry = entry.wrap_err_with(|| format!("failed to read entry in `{path}`"))?;
+        if is_profile_dir(entry.path()) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_profile_sizes() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let target_dir = Utf8Path::from_path(temp_dir.path()).expect("temp dir is UTF-8");
+
+        let files = [
+            ("debug/.fingerprint/foo", 1),
+            ("debug/deps/libfoo.rlib", 10),
+            ("release/.fingerprint/foo", 100),
+            ("release-lto/.fingerprint/foo", 1000),
+            ("doc/foo/index.html", 10_000),
+            ("x86_64-unknown-linux-musl/debug/.fingerprint/foo", 100_000),
+            ("x86_64-unknown-linux-musl/doc/foo/index.html", 1_000_000),
+            ("CACHEDIR.TAG", 10_000_000),
+            ("tmp/foo", 100_000_000),
+        ];
+        for (path, size) in files {
+            let path = target_dir.join(path);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(&path, vec![0; size])?;
+        }
+        // Cargo uplifts artifacts from `deps/` with hard links, which are only counted once.
+        fs::hard_link(
+            target_dir.join("debug/deps/libfoo.rlib"),
+            target_dir.join("debug/libfoo.rlib"),
+        )?;
+
+        let sizes = profile_sizes(target_dir)?;
+        let expected = BTreeMap::from(
+            [
+                ("debug", 11),
+                ("release", 100),
+                ("release-lto", 1000),
+                ("doc", 10_000),
+                ("x86_64-unknown-linux-musl/debug", 100_000),
+                ("x86_64-unknown-linux-musl/doc", 1_000_000),
+                ("other", 110_000_000),
+            ]
+            .map(|(profile, size)| (profile.to_owned(), size)),
+        );
+        assert_eq!(sizes, expected, "profile sizes match");
+
+        Ok(())
+    }
+}
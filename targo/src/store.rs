@@ -1,30 +1,80 @@
 use crate::{
-    helpers::{AsLockedCtx, DirWithPath, ExclusiveRoot, UnlockedRoot},
-    metadata::{TargetDirMetadata, TargoStoreMetadata},
+    config::{GcConfig, Placement, RootConfig, TargoConfig},
+    helpers::{
+        dir_size, format_bytes, move_dir_all, replace_dir_with_copy, replace_symlink,
+        replace_symlink_with_dir, replace_with_hard_link, AsLockedCtx, DirWithPath, ExclusiveRoot,
+        SharedRoot, UnlockedRoot,
+    },
+    metadata::{
+        HistoryRecord, TargetDirMetadata, TargetDirUse, TargoStoreMetadata, ToolchainCache,
+    },
+    output::{EjectReport, GcReport, MoveDirReport, RemovedDir, ShareDepsReport, SyncReport},
+    shared_deps::{content_hash, find_artifacts, shared_inodes, SHARED_DIR_NAME},
+    stats::{profile_sizes, DirStats, RootStats, StoreStats},
 };
 use camino::{Utf8Path, Utf8PathBuf};
 use cap_std::{ambient_authority, fs_utf8::Dir};
-use color_eyre::{eyre::Context, Result};
+use chrono::{DateTime, Local, TimeDelta};
+use color_eyre::{
+    eyre::{bail, Context},
+    Result,
+};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    io,
+    os::unix::fs::MetadataExt,
+};
 use xxhash_rust::xxh3::xxh3_64;
 
 #[derive(Debug)]
 pub(crate) struct TargoStore {
-    store_dir: DirWithPath,
+    /// The roots that managed target directories are stored in.
+    ///
+    /// The first root is always the default root in the store directory, which also holds the
+    /// store's metadata and lock.
+    roots: Vec<StoreRoot>,
+    config: TargoConfig,
+    /// Workspaces that have been ejected, as recorded in the store metadata.
+    opted_out: BTreeSet<Utf8PathBuf>,
 }
 
 impl TargoStore {
-    pub(crate) fn new(store_dir_path: Utf8PathBuf) -> Result<Self> {
-        let authority = ambient_authority();
-        Dir::create_ambient_dir_all(&store_dir_path, authority).wrap_err_with(|| {
-            format!("failed to create targo store directory `{store_dir_path}`")
-        })?;
-        let store_dir = Dir::open_ambient_dir(&store_dir_path, authority)
-            .wrap_err_with(|| format!("failed to open targo store directory `{store_dir_path}`"))?;
-        let store_dir = DirWithPath::new(store_dir, store_dir_path);
+    pub(crate) fn new(store_dir_path: Utf8PathBuf, config: &TargoConfig) -> Result<Self> {
+        let mut roots = vec![StoreRoot::open(
+            RootConfig::DEFAULT_NAME.to_owned(),
+            store_dir_path.clone(),
+            None,
+        )?];
+        for root in &config.roots {
+            let volatile = root.volatile.then(|| {
+                let snapshot_dir = root.persist_to.as_deref().map(|persist_to| {
+                    let persist_path = config
+                        .roots
+                        .iter()
+                        .find(|other| other.name == persist_to)
+                        .map_or(&store_dir_path, |other| &other.path);
+                    persist_path.join(StoreRoot::SNAPSHOTS_DIR_NAME)
+                });
+                VolatileRoot {
+                    snapshot_dir,
+                    min_free_percent: root.min_free_percent,
+                }
+            });
+            roots.push(StoreRoot::open(
+                root.name.clone(),
+                root.path.clone(),
+                volatile,
+            )?);
+        }
 
-        let store = Self { store_dir };
+        let store = Self {
+            roots,
+            config: config.clone(),
+            opted_out: BTreeSet::new(),
+        };
 
-        let store = UnlockedRoot::new(store)?.lock_exclusive()?;
+        let mut store = UnlockedRoot::new(store)?.lock_exclusive()?;
 
         // TODO: hold lock open while TargoStore is held, so per-directory metadata can be written
         // safely
@@ -41,6 +91,9 @@ impl TargoStore {
             // TODO: also upgrade metadata within the directory if required
             Self::write_store_metadata(&store, &to_write)?;
         }
+        if let Some(metadata) = metadata {
+            store.ctx.opted_out = metadata.opted_out;
+        }
 
         Ok(store.unlock())
     }
@@ -52,7 +105,7 @@ impl TargoStore {
     ) -> Result<TargetDirKind> {
         let symlink_metadata = match target_dir.symlink_metadata() {
             Ok(metadata) => metadata,
-            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
                 return Ok(TargetDirKind::DoesNotExist {
                     workspace_dir: workspace_dir.to_owned(),
                     target_dir: target_dir.to_owned(),
@@ -71,23 +124,11 @@ impl TargoStore {
                 workspace_dir: workspace_dir.to_owned(),
                 target_dir: target_dir.to_owned(),
             }
-        } else if symlink_metadata.is_symlink() {
-            // TODO: read link in a TOCTTOU-safe manner
-            let data = target_dir
-                .read_link()
-                .wrap_err_with(|| format!("failed to read `{target_dir}` as symlink"))?;
-            let dest_dir = Utf8PathBuf::try_from(data).wrap_err_with(|| {
-                format!("destination of symlink at `{target_dir}` is invalid UTF-8")
-            })?;
-
-            // Is this a symlink managed by this installation of Targo?
-            // (TODO: be able to operate on other installations of Targo maybe?)
-            if let Some(encoded) = get_encoded_workspace(self.store_dir.path(), &dest_dir) {
-                let managed_dir = ManagedTargetDir::new(self, target_dir.to_owned(), encoded)?;
-                TargetDirKind::TargoSymlink(managed_dir)
-            } else {
-                TargetDirKind::Other
-            }
+        } else if let Some((root, encoded)) =
+            self.targo_symlink_dest(target_dir, &symlink_metadata)?
+        {
+            let managed_dir = ManagedTargetDir::new_symlinked(root, target_dir, &encoded)?;
+            TargetDirKind::TargoSymlink(managed_dir)
         } else {
             TargetDirKind::Other
         };
@@ -95,20 +136,95 @@ impl TargoStore {
         Ok(kind)
     }
 
+    /// Like [`Self::determine_target_dir`], but only inspects the target directory without
+    /// changing anything.
+    pub(crate) fn inspect_target_dir(&self, target_dir: &Utf8Path) -> Result<TargetDirState> {
+        let symlink_metadata = match target_dir.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(TargetDirState::DoesNotExist)
+            }
+            Err(err) => {
+                return Err(err).wrap_err_with(|| {
+                    format!("failed to read metadata for target dir `{target_dir}`")
+                })
+            }
+        };
+
+        let state = if symlink_metadata.is_dir() {
+            TargetDirState::Directory
+        } else if let Some((_, encoded)) = self.targo_symlink_dest(target_dir, &symlink_metadata)? {
+            TargetDirState::TargoSymlink { encoded }
+        } else {
+            TargetDirState::Other
+        };
+        Ok(state)
+    }
+
+    /// Returns the path of the store root containing a managed directory, and the path to the
+    /// managed `target` directory, or `None` if it doesn't exist.
+    pub(crate) fn managed_target_dir_path(
+        &self,
+        encoded: &str,
+    ) -> Option<(&Utf8Path, Utf8PathBuf)> {
+        let root = self.find_existing(encoded)?;
+        Some((
+            root.dir.path(),
+            root.dir.path().join(encoded).join("target"),
+        ))
+    }
+
+    /// Returns the `target-ra` directory for IDE checks in an existing managed directory, creating
+    /// it if necessary, or `None` if the managed directory doesn't exist.
+    ///
+    /// It lives next to the managed `target` directory, so it's garbage collected, moved and
+    /// evicted along with it. Nothing else is changed.
+    pub(crate) fn ra_target_dir(&self, encoded: &str) -> Result<Option<Utf8PathBuf>> {
+        let Some(root) = self.find_existing(encoded) else {
+            return Ok(None);
+        };
+        let dest_dir = root.open_managed(encoded)?;
+        let ra_target_dir = dest_dir.path().join(ManagedTargetDir::RA_TARGET_DIR_NAME);
+        dest_dir
+            .dir()
+            .create_dir_all(ManagedTargetDir::RA_TARGET_DIR_NAME)
+            .wrap_err_with(|| format!("failed to create `{ra_target_dir}`"))?;
+        Ok(Some(ra_target_dir))
+    }
+
+    /// Returns information about a managed directory without changing anything, or `None` if
+    /// it doesn't exist.
+    pub(crate) fn managed_dir_info(&self, encoded: &str) -> Result<Option<ManagedDirInfo>> {
+        let Some(root) = self.find_existing(encoded) else {
+            return Ok(None);
+        };
+        let dest_dir = root.open_managed(encoded)?;
+        let metadata = ManagedTargetDir::read_dir_metadata(&dest_dir)?;
+        let size = dir_size(&dest_dir.path().join("target"))?;
+        Ok(Some(ManagedDirInfo {
+            path: dest_dir.path().to_owned(),
+            root: root.name.clone(),
+            size,
+            last_used: metadata.map(|metadata| metadata.last_used),
+        }))
+    }
+
     pub(crate) fn actualize_kind(&self, kind: TargetDirKind) -> Result<Option<ManagedTargetDir>> {
         match kind {
             TargetDirKind::DoesNotExist {
                 workspace_dir,
                 target_dir,
             } => {
-                let managed_dir = self.setup_target_dir(workspace_dir, target_dir, false)?;
+                let managed_dir =
+                    self.setup_target_dir(workspace_dir, target_dir, ExistingTargetDir::None)?;
                 Ok(Some(managed_dir))
             }
             TargetDirKind::Directory {
                 workspace_dir,
                 target_dir,
             } => {
-                let managed_dir = self.setup_target_dir(workspace_dir, target_dir, true)?;
+                let managed_dir =
+                    self.setup_target_dir(workspace_dir, target_dir, ExistingTargetDir::Discard)?;
                 Ok(Some(managed_dir))
             }
             TargetDirKind::TargoSymlink(managed_dir) => Ok(Some(managed_dir)),
@@ -116,17 +232,485 @@ impl TargoStore {
         }
     }
 
+    /// Moves a workspace's existing `target` directory into the store, replacing it with a
+    /// symlink.
+    ///
+    /// If the workspace isn't writable, the returned directory has [`Placement::Env`] and
+    /// nothing is moved.
+    pub(crate) fn migrate_target_dir(&self, workspace_dir: &Utf8Path) -> Result<ManagedTargetDir> {
+        self.setup_target_dir(
+            workspace_dir.to_owned(),
+            workspace_dir.join("target"),
+            ExistingTargetDir::Move,
+        )
+    }
+
+    /// Returns the target directory for a workspace that's pointed at it via `CARGO_TARGET_DIR`
+    /// rather than a symlink.
+    pub(crate) fn env_target_dir(&self, workspace_dir: &Utf8Path) -> Result<ManagedTargetDir> {
+        let encoded = encode_workspace_path(workspace_dir);
+        let root = self.choose_root(&encoded, Some(workspace_dir), || 0);
+        ManagedTargetDir::new(root, &encoded, Placement::Env, |metadata| {
+            metadata.env_workspaces.insert(workspace_dir.to_owned());
+        })
+    }
+
+    /// Returns the target directory for a build that isn't associated with a workspace, e.g.
+    /// `cargo install` or a Cargo script.
+    pub(crate) fn standalone_target_dir(
+        &self,
+        used_for: &TargetDirUse,
+    ) -> Result<ManagedTargetDir> {
+        let encoded = encode_key(&used_for.key());
+        let path = match used_for {
+            TargetDirUse::Install(_) => None,
+            TargetDirUse::Script { path } => Some(path.as_path()),
+        };
+        let root = self.choose_root(&encoded, path, || 0);
+        ManagedTargetDir::new(root, &encoded, Placement::Env, |metadata| {
+            metadata.used_for = Some(used_for.clone());
+        })
+    }
+
+    /// Removes managed target directories that haven't been used recently.
+    pub(crate) fn gc(self, config: &GcConfig, dry_run: bool) -> Result<GcReport> {
+        let store = UnlockedRoot::new(self)?.lock_exclusive()?;
+        let now = Local::now();
+
+        let mut removed = Vec::new();
+        let mut shared_deps_size = 0;
+        for root in &store.ctx.roots {
+            root.gc(config, now, dry_run, &mut removed)?;
+            shared_deps_size += root.prune_shared_deps(dry_run)?;
+        }
+        let total_size = removed.iter().map(|dir| dir.size).sum();
+
+        let verb = if dry_run { "would free" } else { "freed" };
+        eprintln!(
+            "[targo] gc {verb} {}",
+            format_bytes(total_size + shared_deps_size)
+        );
+
+        store.unlock();
+        Ok(GcReport {
+            dry_run,
+            removed,
+            total_size,
+            shared_deps_size,
+        })
+    }
+
+    /// Stores identical registry dependency artifacts once per root, and links them into managed
+    /// target directories.
+    ///
+    /// Directories in use by a build are skipped.
+    pub(crate) fn share_deps(
+        self,
+        registry_src: &Utf8Path,
+        dry_run: bool,
+    ) -> Result<ShareDepsReport> {
+        let store = UnlockedRoot::new(self)?.lock_exclusive()?;
+        let mut report = ShareDepsReport {
+            dry_run,
+            ..ShareDepsReport::default()
+        };
+        for root in &store.ctx.roots {
+            root.share_deps(registry_src, dry_run, &mut report)?;
+        }
+        store.unlock();
+        Ok(report)
+    }
+
+    /// Moves the managed target directory for a workspace to another root, re-pointing its
+    /// backlinks.
+    pub(crate) fn move_dir(
+        self,
+        workspace_dir: &Utf8Path,
+        root_name: &str,
+    ) -> Result<MoveDirReport> {
+        let store = UnlockedRoot::new(self)?.lock_exclusive()?;
+        let encoded = encode_workspace_path(workspace_dir);
+
+        let Some(src_root) = store.ctx.find_existing(&encoded) else {
+            bail!("workspace `{workspace_dir}` doesn't have a managed target directory");
+        };
+        let Some(dest_root) = store.ctx.root(root_name) else {
+            bail!("unknown store root `{root_name}`");
+        };
+        let src_path = src_root.dir.path().join(&encoded);
+        let dest_path = dest_root.dir.path().join(&encoded);
+        let report = MoveDirReport {
+            workspace: workspace_dir.to_owned(),
+            from_root: src_root.name.clone(),
+            to_root: dest_root.name.clone(),
+            path: dest_path.clone(),
+        };
+        if src_root.name == dest_root.name {
+            eprintln!("[targo] `{src_path}` is already in root `{root_name}`");
+            store.unlock();
+            return Ok(report);
+        }
+        if dest_root.dir.dir().exists(&encoded) {
+            bail!("`{dest_path}` already exists");
+        }
+
+        move_dir_all(&src_path, &dest_path)?;
+
+        // Re-point symlinks to the new location.
+        let dest_dir = dest_root.open_managed(&encoded)?;
+        if let Some(metadata) = ManagedTargetDir::read_dir_metadata(&dest_dir)? {
+            let old_target_dir = src_path.join("target");
+            let new_target_dir = dest_path.join("target");
+            for backlink in &metadata.backlinks {
+                if backlink.read_link_utf8().ok().as_deref() == Some(&*old_target_dir) {
+                    replace_symlink(backlink, &new_target_dir)?;
+                }
+            }
+        }
+
+        eprintln!("[targo] moved `{src_path}` to `{dest_path}`");
+        store.unlock();
+        Ok(report)
+    }
+
+    /// Saves a snapshot of a workspace's target directory in a volatile root to its persistent
+    /// root.
+    pub(crate) fn sync_snapshot(self, workspace_dir: &Utf8Path) -> Result<SyncReport> {
+        let store = UnlockedRoot::new(self)?.lock_exclusive()?;
+        let encoded = encode_workspace_path(workspace_dir);
+
+        let Some(root) = store.ctx.find_existing(&encoded) else {
+            bail!("workspace `{workspace_dir}` doesn't have a managed target directory");
+        };
+        let Some(snapshot_dir) = root
+            .volatile
+            .as_ref()
+            .and_then(|volatile| volatile.snapshot_dir.as_ref())
+        else {
+            bail!(
+                "root `{}` isn't a volatile root with persist-to configured",
+                root.name
+            );
+        };
+
+        let src_path = root.dir.path().join(&encoded);
+        let snapshot_path = snapshot_dir.join(&encoded);
+        std::fs::create_dir_all(snapshot_dir)
+            .wrap_err_with(|| format!("failed to create snapshot directory `{snapshot_dir}`"))?;
+        replace_dir_with_copy(&src_path, &snapshot_path)?;
+
+        let size = dir_size(&snapshot_path)?;
+        eprintln!(
+            "[targo] saved snapshot of `{src_path}` to `{snapshot_path}` ({})",
+            format_bytes(size)
+        );
+        store.unlock();
+        Ok(SyncReport {
+            workspace: workspace_dir.to_owned(),
+            source: src_path,
+            snapshot: snapshot_path,
+            size,
+        })
+    }
+
+    /// Collects usage statistics for the store.
+    ///
+    /// `top` limits the number of directories listed as largest and most recently used.
+    pub(crate) fn stats(self, unused_days: u32, top: usize) -> Result<StoreStats> {
+        let store = UnlockedRoot::new(self)?.lock_shared()?;
+        let store_dir = &store.ctx.default_root().dir;
+        let store_metadata = store_dir.read_metadata(TargoStoreMetadata::METADATA_FILE_NAME)?;
+        let unused_cutoff = Local::now() - TimeDelta::days(i64::from(unused_days));
+
+        let mut roots = Vec::new();
+        let mut dirs = Vec::new();
+        let mut profiles = BTreeMap::new();
+        for root in &store.ctx.roots {
+            let root_path = root.dir.path();
+            let fs_stats = fs2::statvfs(root_path)
+                .wrap_err_with(|| format!("failed to get free space for `{root_path}`"))?;
+            let mut root_stats = RootStats {
+                name: root.name.clone(),
+                path: root_path.to_owned(),
+                volatile: root.volatile.is_some(),
+                dir_count: 0,
+                size: 0,
+                filesystem_total: fs_stats.total_space(),
+                filesystem_free: fs_stats.available_space(),
+            };
+
+            for entry in root.managed_dirs()? {
+                let mut size = 0;
+                for (profile, profile_size) in profile_sizes(&entry.dir.path().join("target"))? {
+                    size += profile_size;
+                    *profiles.entry(profile).or_default() += profile_size;
+                }
+
+                let ManagedDirEntry { dir, metadata, .. } = entry;
+                let workspaces = metadata
+                    .backlinks
+                    .iter()
+                    .filter_map(|backlink| backlink.parent())
+                    .map(ToOwned::to_owned)
+                    .chain(metadata.env_workspaces)
+                    .collect();
+                root_stats.dir_count += 1;
+                root_stats.size += size;
+                dirs.push(DirStats {
+                    path: dir.path().to_owned(),
+                    root: root.name.clone(),
+                    workspaces,
+                    used_for: metadata.used_for,
+                    size,
+                    last_used: metadata.last_used,
+                });
+            }
+            roots.push(root_stats);
+        }
+        store.unlock();
+
+        let mut largest = dirs.clone();
+        largest.sort_by_key(|dir| Reverse(dir.size));
+        largest.truncate(top);
+
+        dirs.sort_by_key(|dir| Reverse(dir.last_used));
+        let total_size = dirs.iter().map(|dir| dir.size).sum();
+        let dir_count = dirs.len();
+        let mut unused: Vec<_> = dirs
+            .iter()
+            .filter(|dir| dir.last_used < unused_cutoff)
+            .cloned()
+            .collect();
+        unused.reverse();
+        let mut most_recently_used = dirs;
+        most_recently_used.truncate(top);
+
+        Ok(StoreStats {
+            store_metadata,
+            roots,
+            dir_count,
+            total_size,
+            largest,
+            most_recently_used,
+            unused_days,
+            unused,
+            profiles,
+        })
+    }
+
+    /// Returns true if the workspace has been ejected, so targo should leave it alone.
+    pub(crate) fn is_opted_out(&self, workspace_dir: &Utf8Path) -> bool {
+        self.opted_out.contains(workspace_dir)
+    }
+
+    /// Removes a workspace from the list of ejected workspaces, so that targo manages it again.
+    pub(crate) fn clear_opt_out(self, workspace_dir: &Utf8Path) -> Result<Self> {
+        if !self.is_opted_out(workspace_dir) {
+            return Ok(self);
+        }
+        let mut store = UnlockedRoot::new(self)?.lock_exclusive()?;
+        let mut store_metadata =
+            Self::read_store_metadata(&store)?.unwrap_or_else(TargoStoreMetadata::new);
+        store_metadata.opted_out.remove(workspace_dir);
+        Self::write_store_metadata(&store, &store_metadata)?;
+        store.ctx.opted_out = store_metadata.opted_out;
+        Ok(store.unlock())
+    }
+
+    /// Moves a workspace's managed target directory back into the workspace as a real
+    /// directory, and records that targo should leave the workspace alone from now on.
+    ///
+    /// This handles both symlinked workspaces and those pointed at the store with
+    /// `CARGO_TARGET_DIR`.
+    pub(crate) fn eject(self, workspace_dir: &Utf8Path) -> Result<EjectReport> {
+        let store = UnlockedRoot::new(self)?.lock_exclusive()?;
+        let target_dir = workspace_dir.join("target");
+
+        let state = store.ctx.inspect_target_dir(&target_dir)?;
+        let (encoded, symlinked) = match state {
+            TargetDirState::TargoSymlink { encoded } => (Some(encoded), true),
+            TargetDirState::Other => {
+                bail!("`{target_dir}` isn't managed by targo, not ejecting");
+            }
+            TargetDirState::DoesNotExist | TargetDirState::Directory => {
+                let encoded = encode_workspace_path(workspace_dir);
+                match store.ctx.env_managed_dir(&encoded, workspace_dir)? {
+                    true if state == TargetDirState::Directory => {
+                        bail!(
+                            "`{target_dir}` already exists, so the managed directory for \
+                             `{workspace_dir}` can't be moved back there"
+                        );
+                    }
+                    true => (Some(encoded), false),
+                    // Nothing to move back, but the opt-out is still recorded.
+                    false => (None, false),
+                }
+            }
+        };
+
+        let mut report = EjectReport {
+            workspace: workspace_dir.to_owned(),
+            target_dir: target_dir.clone(),
+            moved_from: None,
+            size: 0,
+        };
+        if let Some(encoded) = encoded {
+            let Some(root) = store.ctx.find_existing(&encoded) else {
+                bail!("managed directory for `{target_dir}` is missing from the store");
+            };
+            let dest_dir = root.open_managed(&encoded)?;
+            let Some(_in_use) = UnlockedRoot::new(InUseLock(&dest_dir))?.try_lock_exclusive()?
+            else {
+                bail!("`{}` is in use by a build, not ejecting", dest_dir.path());
+            };
+
+            // For symlinks, move the managed directory next to the symlink first, so that the
+            // symlink can be swapped out for it in one step.
+            let managed_target_dir = dest_dir.path().join("target");
+            let tmp_dir = workspace_dir.join(format!(".targo-eject-{}", std::process::id()));
+            let move_to = if symlinked { &tmp_dir } else { &target_dir };
+            if managed_target_dir.exists() {
+                move_dir_all(&managed_target_dir, move_to)?;
+            } else {
+                // A volatile root may have lost the directory.
+                std::fs::create_dir(move_to)
+                    .wrap_err_with(|| format!("failed to create `{move_to}`"))?;
+            }
+            if symlinked {
+                replace_symlink_with_dir(&target_dir, &tmp_dir)?;
+            }
+            report.size = dir_size(&target_dir)?;
+            report.moved_from = Some(managed_target_dir);
+
+            // Forget about this workspace, removing the managed directory if nothing else uses
+            // it.
+            let mut metadata = ManagedTargetDir::read_dir_metadata(&dest_dir)?
+                .unwrap_or_else(TargetDirMetadata::new);
+            metadata.backlinks.remove(&target_dir);
+            metadata.env_workspaces.remove(workspace_dir);
+            if metadata.backlinks.is_empty() && metadata.env_workspaces.is_empty() {
+                root.dir
+                    .dir()
+                    .remove_dir_all(&encoded)
+                    .wrap_err_with(|| format!("failed to remove `{}`", dest_dir.path()))?;
+                root.remove_snapshot(&encoded)?;
+            } else {
+                ManagedTargetDir::write_dir_metadata(&dest_dir, &metadata)?;
+            }
+        }
+
+        let mut store_metadata =
+            Self::read_store_metadata(&store)?.unwrap_or_else(TargoStoreMetadata::new);
+        store_metadata.opted_out.insert(workspace_dir.to_owned());
+        Self::write_store_metadata(&store, &store_metadata)?;
+
+        match &report.moved_from {
+            Some(moved_from) => eprintln!(
+                "[targo] moved `{moved_from}` back to `{target_dir}` ({}), \
+                 targo will leave `{workspace_dir}` alone from now on",
+                format_bytes(report.size)
+            ),
+            None => eprintln!("[targo] targo will leave `{workspace_dir}` alone from now on"),
+        }
+        store.unlock();
+        Ok(report)
+    }
+
+    /// Returns the build history of a workspace's managed target directory, oldest first.
+    pub(crate) fn history(&self, workspace_dir: &Utf8Path) -> Result<Vec<HistoryRecord>> {
+        let encoded = encode_workspace_path(workspace_dir);
+        let Some(root) = self.find_existing(&encoded) else {
+            bail!("workspace `{workspace_dir}` doesn't have a managed target directory");
+        };
+        root.open_managed(&encoded)?
+            .read_jsonl(HistoryRecord::HISTORY_FILE_NAME)
+    }
+
     // ---
     // Helper methods
     // ---
 
+    /// Returns true if the managed directory `encoded` exists and is used by the workspace via
+    /// `CARGO_TARGET_DIR`.
+    fn env_managed_dir(&self, encoded: &str, workspace_dir: &Utf8Path) -> Result<bool> {
+        let Some(root) = self.find_existing(encoded) else {
+            return Ok(false);
+        };
+        let metadata = ManagedTargetDir::read_dir_metadata(&root.open_managed(encoded)?)?;
+        Ok(metadata.is_some_and(|metadata| metadata.env_workspaces.contains(workspace_dir)))
+    }
+
+    fn default_root(&self) -> &StoreRoot {
+        &self.roots[0]
+    }
+
+    fn root(&self, name: &str) -> Option<&StoreRoot> {
+        self.roots.iter().find(|root| root.name == name)
+    }
+
+    /// If `target_dir` is a symlink managed by this store, returns the root and encoded name it
+    /// points to.
+    fn targo_symlink_dest(
+        &self,
+        target_dir: &Utf8Path,
+        symlink_metadata: &std::fs::Metadata,
+    ) -> Result<Option<(&StoreRoot, String)>> {
+        if !symlink_metadata.is_symlink() {
+            return Ok(None);
+        }
+        // TODO: read link in a TOCTTOU-safe manner
+        let data = target_dir
+            .read_link()
+            .wrap_err_with(|| format!("failed to read `{target_dir}` as symlink"))?;
+        let dest_dir = Utf8PathBuf::try_from(data).wrap_err_with(|| {
+            format!("destination of symlink at `{target_dir}` is invalid UTF-8")
+        })?;
+
+        // Is this a symlink managed by this installation of Targo?
+        // (TODO: be able to operate on other installations of Targo maybe?)
+        Ok(self
+            .find_symlink_dest(&dest_dir)
+            .map(|(root, encoded)| (root, encoded.to_owned())))
+    }
+
+    /// Returns the root and encoded workspace for a symlink destination within any root.
+    fn find_symlink_dest<'b>(&self, dest_dir: &'b Utf8Path) -> Option<(&StoreRoot, &'b str)> {
+        self.roots.iter().find_map(|root| {
+            get_encoded_workspace(root.dir.path(), dest_dir).map(|encoded| (root, encoded))
+        })
+    }
+
+    /// Returns the root that already contains a managed directory with this name, if any.
+    fn find_existing(&self, encoded: &str) -> Option<&StoreRoot> {
+        self.roots
+            .iter()
+            .find(|root| root.dir.dir().is_dir(encoded))
+    }
+
+    /// Returns the root to use for a managed directory.
+    ///
+    /// An existing directory stays in the root it's in. New directories are placed according
+    /// to the configured rules.
+    fn choose_root(
+        &self,
+        encoded: &str,
+        path: Option<&Utf8Path>,
+        size: impl FnOnce() -> u64,
+    ) -> &StoreRoot {
+        if let Some(root) = self.find_existing(encoded) {
+            return root;
+        }
+        let name = self.config.root_for(path, size);
+        // The config is validated to only refer to known roots.
+        self.root(name).unwrap_or_else(|| self.default_root())
+    }
+
     fn read_store_metadata(store: &ExclusiveRoot<Self>) -> Result<Option<TargoStoreMetadata>> {
-        let metadata: Option<TargoStoreMetadata> = store
-            .ctx
-            .store_dir
-            .read_metadata(TargoStoreMetadata::METADATA_FILE_NAME)?;
+        let store_dir = &store.ctx.default_root().dir;
+        let metadata: Option<TargoStoreMetadata> =
+            store_dir.read_metadata(TargoStoreMetadata::METADATA_FILE_NAME)?;
         let metadata = if let Some(metadata) = metadata {
-            Some(metadata.verify(store.ctx.store_dir.path())?)
+            Some(metadata.verify(store_dir.path())?)
         } else {
             None
         };
@@ -139,7 +723,8 @@ impl TargoStore {
     ) -> Result<()> {
         store
             .ctx
-            .store_dir
+            .default_root()
+            .dir
             .write_metadata(TargoStoreMetadata::METADATA_FILE_NAME, metadata)
     }
 
@@ -147,47 +732,520 @@ impl TargoStore {
         &self,
         workspace_dir: Utf8PathBuf,
         target_dir: Utf8PathBuf,
-        exists: bool,
+        existing: ExistingTargetDir,
     ) -> Result<ManagedTargetDir> {
-        if exists {
-            // TODO: do something better than rm -rf target/ here!
-            match std::fs::remove_dir_all(&target_dir) {
-                Ok(()) => {}
-                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-                    // The directory doesn't exist. Skip this.
-                }
-                Err(err) => {
-                    Err::<(), _>(err).wrap_err_with(|| {
-                        format!("failed to remove old target dir `{target_dir}`")
-                    })?;
-                }
+        let encoded = target_dir_key(&workspace_dir, &target_dir);
+        let root = self.choose_root(&encoded, Some(&workspace_dir), || match existing {
+            ExistingTargetDir::None => 0,
+            ExistingTargetDir::Discard | ExistingTargetDir::Move => {
+                dir_size(&target_dir).unwrap_or(0)
+            }
+        });
+        let dest_target_dir = root.dir.path().join(&encoded).join("target");
+
+        // Create the symlink under a temporary name first. This checks that the workspace is
+        // writable before anything in it is removed.
+        // TODO: Windows
+        let tmp_link = workspace_dir.join(format!(".targo-tmp-{}", std::process::id()));
+        match std::os::unix::fs::symlink(&dest_target_dir, &tmp_link) {
+            Ok(()) => {}
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem
+                ) =>
+            {
+                // The workspace can't be written to (e.g. a read-only source mount), so point
+                // Cargo at the managed directory via the environment instead.
+                tracing::debug!("workspace `{workspace_dir}` is not writable: {err}");
+                return self.env_target_dir(&workspace_dir);
+            }
+            Err(err) => {
+                return Err(err).wrap_err_with(|| {
+                    format!("failed to create symlink from `{tmp_link}` to `{dest_target_dir}`")
+                });
             }
         }
 
-        // Create the managed target directory and symlink.
-        let encoded = encode_workspace_path(&workspace_dir);
-        let managed_dir = ManagedTargetDir::new(self, target_dir, &encoded)?;
+        let result = (|| {
+            if existing == ExistingTargetDir::Discard {
+                // TODO: do something better than rm -rf target/ here!
+                match std::fs::remove_dir_all(&target_dir) {
+                    Ok(()) => {}
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                        // The directory doesn't exist. Skip this.
+                    }
+                    Err(err) => {
+                        return Err(err).wrap_err_with(|| {
+                            format!("failed to remove old target dir `{target_dir}`")
+                        });
+                    }
+                }
+            }
 
-        // Create the symlink.
-        // TODO: Windows
-        std::os::unix::fs::symlink(&managed_dir.target_dir, &managed_dir.source_link)
-            .wrap_err_with(|| {
-                format!(
-                    "failed to create symlink from `{}` to `{}`",
-                    managed_dir.source_link, managed_dir.target_dir
-                )
+            // Create the managed target directory, and move the symlink into place.
+            let managed_dir = ManagedTargetDir::new_symlinked(root, &target_dir, &encoded)?;
+            if existing == ExistingTargetDir::Move {
+                // The existing directory replaces the empty (or restored) one in the store.
+                let dest = managed_dir.target_dir();
+                std::fs::remove_dir_all(dest)
+                    .wrap_err_with(|| format!("failed to remove `{dest}`"))?;
+                move_dir_all(&target_dir, dest)?;
+            }
+            std::fs::rename(&tmp_link, &target_dir).wrap_err_with(|| {
+                format!("failed to rename symlink `{tmp_link}` to `{target_dir}`")
             })?;
+            Ok(managed_dir)
+        })();
 
-        Ok(managed_dir)
+        // Don't leave the temporary symlink behind in the workspace.
+        if result.is_err() {
+            _ = std::fs::remove_file(&tmp_link);
+        }
+        result
     }
 }
 
 impl AsLockedCtx for TargoStore {
     fn dir_and_lock_name(&self) -> (&DirWithPath, &str) {
-        (&self.store_dir, "targo.lock")
+        (&self.default_root().dir, "targo.lock")
     }
 }
 
+/// A directory that managed target directories are stored in.
+#[derive(Debug)]
+pub(crate) struct StoreRoot {
+    name: String,
+    dir: DirWithPath,
+    /// Set for roots on volatile filesystems like tmpfs.
+    volatile: Option<VolatileRoot>,
+}
+
+#[derive(Debug)]
+struct VolatileRoot {
+    /// Where snapshots of target directories in this root are saved, if configured.
+    snapshot_dir: Option<Utf8PathBuf>,
+    min_free_percent: u8,
+}
+
+impl StoreRoot {
+    /// The directory within a persistent root that snapshots of volatile target directories are
+    /// saved to.
+    ///
+    /// Encoded names never start with `.`, so this can't collide with a managed directory.
+    const SNAPSHOTS_DIR_NAME: &'static str = ".targo-snapshots";
+
+    /// How long a target directory must go unused before it can be evicted.
+    ///
+    /// In exec mode, targo can't hold the in-use lock while Cargo runs, so a directory used more
+    /// recently than this may still be in use by a build.
+    const EVICTION_MIN_IDLE: TimeDelta = TimeDelta::hours(1);
+
+    fn open(name: String, path: Utf8PathBuf, volatile: Option<VolatileRoot>) -> Result<Self> {
+        let authority = ambient_authority();
+        Dir::create_ambient_dir_all(&path, authority)
+            .wrap_err_with(|| format!("failed to create targo store directory `{path}`"))?;
+        let dir = Dir::open_ambient_dir(&path, authority)
+            .wrap_err_with(|| format!("failed to open targo store directory `{path}`"))?;
+        Ok(Self {
+            name,
+            dir: DirWithPath::new(dir, path),
+            volatile,
+        })
+    }
+
+    /// Returns all the managed target directories in this root.
+    fn managed_dirs(&self) -> Result<Vec<ManagedDirEntry>> {
+        let root_path = self.dir.path();
+        let entries = self
+            .dir
+            .dir()
+            .entries()
+            .wrap_err_with(|| format!("failed to read store directory `{root_path}`"))?;
+        let mut managed_dirs = Vec::new();
+        for entry in entries {
+            let entry = entry.wrap_err_with(|| {
+                format!("failed to read entry in store directory `{root_path}`")
+            })?;
+            let encoded = entry
+                .file_name()
+                .wrap_err_with(|| format!("non-UTF-8 entry in store directory `{root_path}`"))?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let dir = self.open_managed(&encoded)?;
+            let Some(metadata) = ManagedTargetDir::read_dir_metadata(&dir)? else {
+                // Not a managed target directory.
+                continue;
+            };
+            managed_dirs.push(ManagedDirEntry {
+                encoded,
+                dir,
+                metadata,
+            });
+        }
+
+        Ok(managed_dirs)
+    }
+
+    fn share_deps(
+        &self,
+        registry_src: &Utf8Path,
+        dry_run: bool,
+        report: &mut ShareDepsReport,
+    ) -> Result<()> {
+        let mut sharer = DepsSharer::new(self.dir.path(), registry_src, dry_run, report)?;
+        for entry in self.managed_dirs()? {
+            let Some(_in_use) = UnlockedRoot::new(InUseLock(&entry.dir))?.try_lock_exclusive()?
+            else {
+                eprintln!(
+                    "[targo] skipping `{}`, which is in use by a build",
+                    entry.dir.path()
+                );
+                continue;
+            };
+            sharer.share(&entry.dir.path().join("target"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes shared dependency artifacts that no managed directory links to anymore, returning
+    /// their total size.
+    ///
+    /// Dry runs only count artifacts that are already unused, not ones that would become unused
+    /// once garbage collected directories are removed.
+    fn prune_shared_deps(&self, dry_run: bool) -> Result<u64> {
+        let shared_dir = self.dir.path().join(SHARED_DIR_NAME);
+        let Ok(prefixes) = shared_dir.read_dir_utf8() else {
+            return Ok(0);
+        };
+
+        let mut size = 0;
+        for prefix in prefixes {
+            let prefix = prefix.wrap_err_with(|| format!("failed to read `{shared_dir}`"))?;
+            let entries = prefix
+                .path()
+                .read_dir_utf8()
+                .wrap_err_with(|| format!("failed to read `{}`", prefix.path()))?;
+            for entry in entries {
+                let entry =
+                    entry.wrap_err_with(|| format!("failed to read `{}`", prefix.path()))?;
+                let metadata = entry
+                    .metadata()
+                    .wrap_err_with(|| format!("failed to read metadata for `{}`", entry.path()))?;
+                if metadata.nlink() > 1 {
+                    continue;
+                }
+                if !dry_run {
+                    std::fs::remove_file(entry.path())
+                        .wrap_err_with(|| format!("failed to remove `{}`", entry.path()))?;
+                }
+                size += metadata.len();
+            }
+        }
+
+        Ok(size)
+    }
+
+    /// For volatile roots, restores a target directory that has vanished from its snapshot, if
+    /// there is one.
+    fn restore_snapshot(&self, encoded: &str) -> Result<()> {
+        let Some(snapshot_dir) = self
+            .volatile
+            .as_ref()
+            .and_then(|volatile| volatile.snapshot_dir.as_ref())
+        else {
+            return Ok(());
+        };
+        let snapshot_path = snapshot_dir.join(encoded);
+        if !snapshot_path.is_dir() {
+            return Ok(());
+        }
+
+        let dest_path = self.dir.path().join(encoded);
+        replace_dir_with_copy(&snapshot_path, &dest_path)?;
+        eprintln!("[targo] restored `{dest_path}` from snapshot `{snapshot_path}`");
+        Ok(())
+    }
+
+    fn remove_snapshot(&self, encoded: &str) -> Result<()> {
+        let Some(snapshot_dir) = self
+            .volatile
+            .as_ref()
+            .and_then(|volatile| volatile.snapshot_dir.as_ref())
+        else {
+            return Ok(());
+        };
+        let snapshot_path = snapshot_dir.join(encoded);
+        match std::fs::remove_dir_all(&snapshot_path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => {
+                Err(err).wrap_err_with(|| format!("failed to remove snapshot `{snapshot_path}`"))
+            }
+        }
+    }
+
+    /// For volatile roots, evicts the least recently used target directories (other than
+    /// `keep` and those used recently) while the filesystem is low on free space.
+    fn evict_if_low_on_space(&self, keep: &str) -> Result<()> {
+        let Some(volatile) = &self.volatile else {
+            return Ok(());
+        };
+        let root_path = self.dir.path();
+        let is_low_on_space = || -> Result<bool> {
+            let stats = fs2::statvfs(root_path)
+                .wrap_err_with(|| format!("failed to get free space for `{root_path}`"))?;
+            Ok(stats.available_space() * 100
+                < stats.total_space() * u64::from(volatile.min_free_percent))
+        };
+        if !is_low_on_space()? {
+            return Ok(());
+        }
+
+        let now = Local::now();
+        let mut managed_dirs = self.managed_dirs()?;
+        managed_dirs.sort_by_key(|entry| entry.metadata.last_used);
+        for entry in managed_dirs {
+            if entry.encoded == keep
+                || now.signed_duration_since(entry.metadata.last_used) < Self::EVICTION_MIN_IDLE
+            {
+                continue;
+            }
+            let Some(_in_use) = UnlockedRoot::new(InUseLock(&entry.dir))?.try_lock_exclusive()?
+            else {
+                continue;
+            };
+            // Backlinks are left alone, since the directory is recreated the next time it's
+            // used, just like after a reboot.
+            let size = dir_size(entry.dir.path())?;
+            self.dir
+                .dir()
+                .remove_dir_all(&entry.encoded)
+                .wrap_err_with(|| format!("failed to evict `{}`", entry.dir.path()))?;
+            eprintln!(
+                "[targo] evicted `{}` from volatile root `{}` ({})",
+                entry.dir.path(),
+                self.name,
+                format_bytes(size)
+            );
+            if !is_low_on_space()? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn open_managed(&self, encoded: &str) -> Result<DirWithPath> {
+        let path = self.dir.path().join(encoded);
+        let dir = self
+            .dir
+            .dir()
+            .open_dir(encoded)
+            .wrap_err_with(|| format!("failed to open managed target directory `{path}`"))?;
+        Ok(DirWithPath::new(dir, path))
+    }
+
+    /// Removes managed target directories in this root that haven't been used recently, adding
+    /// them to `removed`.
+    fn gc(
+        &self,
+        config: &GcConfig,
+        now: DateTime<Local>,
+        dry_run: bool,
+        removed: &mut Vec<RemovedDir>,
+    ) -> Result<()> {
+        for ManagedDirEntry {
+            encoded,
+            dir: dest_dir,
+            metadata,
+        } in self.managed_dirs()?
+        {
+            let max_age = config.max_age(metadata.used_for.as_ref());
+            if now.signed_duration_since(metadata.last_used) < max_age {
+                continue;
+            }
+            // Hold the in-use lock while removing the directory, so a build can't start using
+            // it halfway through.
+            let Some(_in_use) = UnlockedRoot::new(InUseLock(&dest_dir))?.try_lock_exclusive()?
+            else {
+                eprintln!(
+                    "[targo] skipping `{}`, which is in use by a build",
+                    dest_dir.path()
+                );
+                continue;
+            };
+
+            let dest_dir_path = dest_dir.path();
+            let size = dir_size(dest_dir_path)?;
+            if dry_run {
+                eprintln!(
+                    "[targo] would remove `{dest_dir_path}` ({})",
+                    format_bytes(size)
+                );
+                removed.push(RemovedDir {
+                    path: dest_dir_path.to_owned(),
+                    root: self.name.clone(),
+                    size,
+                    metadata,
+                });
+                continue;
+            }
+
+            // Remove backlinks that still point to this directory, so that they don't dangle.
+            let target_dir = dest_dir_path.join("target");
+            for backlink in &metadata.backlinks {
+                if backlink.read_link_utf8().ok().as_deref() == Some(&*target_dir) {
+                    std::fs::remove_file(backlink)
+                        .wrap_err_with(|| format!("failed to remove symlink `{backlink}`"))?;
+                }
+            }
+            self.dir
+                .dir()
+                .remove_dir_all(&encoded)
+                .wrap_err_with(|| format!("failed to remove `{dest_dir_path}`"))?;
+            self.remove_snapshot(&encoded)?;
+            eprintln!("[targo] removed `{dest_dir_path}` ({})", format_bytes(size));
+            removed.push(RemovedDir {
+                path: dest_dir_path.to_owned(),
+                root: self.name.clone(),
+                size,
+                metadata,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Links registry dependency artifacts in a store root to its shared directory.
+struct DepsSharer<'a> {
+    shared_dir: Utf8PathBuf,
+    registry_src: &'a Utf8Path,
+    dry_run: bool,
+    /// The device and inode numbers of artifacts in the shared directory.
+    shared_inodes: HashSet<(u64, u64)>,
+    /// For dry runs, artifacts that would have been stored by now.
+    would_store: HashSet<Utf8PathBuf>,
+    report: &'a mut ShareDepsReport,
+}
+
+impl<'a> DepsSharer<'a> {
+    fn new(
+        root_dir: &Utf8Path,
+        registry_src: &'a Utf8Path,
+        dry_run: bool,
+        report: &'a mut ShareDepsReport,
+    ) -> Result<Self> {
+        let shared_dir = root_dir.join(SHARED_DIR_NAME);
+        let shared_inodes = shared_inodes(&shared_dir)?;
+        Ok(Self {
+            shared_dir,
+            registry_src,
+            dry_run,
+            shared_inodes,
+            would_store: HashSet::new(),
+            report,
+        })
+    }
+
+    /// Shares the artifacts in a `target` directory, which must be locked against builds.
+    fn share(&mut self, target_dir: &Utf8Path) -> Result<()> {
+        for artifact in find_artifacts(target_dir, self.registry_src)? {
+            self.report.scanned += 1;
+            let metadata = artifact
+                .symlink_metadata()
+                .wrap_err_with(|| format!("failed to read metadata for `{artifact}`"))?;
+            // Skip hashing artifacts that are already shared.
+            let inode = (metadata.dev(), metadata.ino());
+            if metadata.nlink() > 1 && self.shared_inodes.contains(&inode) {
+                self.report.already_shared += 1;
+                continue;
+            }
+
+            let hash = content_hash(&artifact)?;
+            let shared_path = self.shared_dir.join(&hash[..2]).join(&hash);
+            let shared_exists = match shared_path.metadata() {
+                Ok(_) => true,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    self.would_store.contains(&shared_path)
+                }
+                Err(err) => {
+                    return Err(err)
+                        .wrap_err_with(|| format!("failed to read metadata for `{shared_path}`"));
+                }
+            };
+
+            if shared_exists {
+                if !self.dry_run {
+                    replace_with_hard_link(&shared_path, &artifact)?;
+                }
+                self.report.linked += 1;
+                self.report.saved_size += metadata.len();
+            } else {
+                if self.dry_run {
+                    self.would_store.insert(shared_path);
+                } else {
+                    let parent = shared_path.parent().expect("shared path has a parent");
+                    std::fs::create_dir_all(parent)
+                        .wrap_err_with(|| format!("failed to create `{parent}`"))?;
+                    replace_with_hard_link(&artifact, &shared_path)?;
+                    self.shared_inodes.insert(inode);
+                }
+                self.report.stored += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A managed target directory found within a root.
+#[derive(Debug)]
+struct ManagedDirEntry {
+    encoded: String,
+    dir: DirWithPath,
+    metadata: TargetDirMetadata,
+}
+
+/// What's at a workspace's target directory path, as determined by
+/// [`TargoStore::inspect_target_dir`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum TargetDirState {
+    DoesNotExist,
+    Directory,
+    /// A symlink to a managed directory in this store.
+    TargoSymlink {
+        encoded: String,
+    },
+    /// Includes non-Targo symlinks and other situations that won't be touched.
+    Other,
+}
+
+/// Information about a managed directory, as returned by [`TargoStore::managed_dir_info`].
+#[derive(Debug)]
+pub(crate) struct ManagedDirInfo {
+    pub(crate) path: Utf8PathBuf,
+    pub(crate) root: String,
+    /// The size of the `target` directory in bytes.
+    pub(crate) size: u64,
+    pub(crate) last_used: Option<DateTime<Local>>,
+}
+
+/// What to do with an existing target directory when setting up a managed one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExistingTargetDir {
+    /// There's no existing directory.
+    None,
+    /// Remove the existing directory.
+    Discard,
+    /// Move the existing directory into the store.
+    Move,
+}
+
 #[derive(Debug)]
 pub(crate) enum TargetDirKind {
     DoesNotExist {
@@ -203,47 +1261,151 @@ pub(crate) enum TargetDirKind {
     Other,
 }
 
+/// The lock on a managed target directory that's held (shared) for as long as Cargo is running
+/// against it. Garbage collection and eviction skip directories where it can't be obtained
+/// exclusively.
+#[derive(Debug)]
+pub(crate) struct InUseLock<'a>(&'a DirWithPath);
+
+impl InUseLock<'_> {
+    const LOCK_NAME: &'static str = "targo-in-use.lock";
+}
+
+impl AsLockedCtx for InUseLock<'_> {
+    fn dir_and_lock_name(&self) -> (&DirWithPath, &str) {
+        (self.0, Self::LOCK_NAME)
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct ManagedTargetDir {
-    source_link: Utf8PathBuf,
-    #[allow(dead_code)]
     dest_dir: DirWithPath,
     target_dir: Utf8PathBuf,
+    placement: Placement,
 }
 
 impl ManagedTargetDir {
-    fn new(store: &TargoStore, source_link: Utf8PathBuf, encoded: &str) -> Result<Self> {
-        // Create the directory if it doesn't exist.
-        let dest_dir_path = store.store_dir.path().join(encoded);
+    const RA_TARGET_DIR_NAME: &'static str = "target-ra";
+
+    /// The path to the `target` directory within the store.
+    pub(crate) fn target_dir(&self) -> &Utf8Path {
+        &self.target_dir
+    }
+
+    /// How Cargo is pointed at this directory.
+    ///
+    /// With [`Placement::Env`], `CARGO_TARGET_DIR` must be set to [`Self::target_dir`].
+    pub(crate) fn placement(&self) -> Placement {
+        self.placement
+    }
+
+    /// Returns the current size of the target directory in bytes.
+    pub(crate) fn size(&self) -> Result<u64> {
+        dir_size(&self.target_dir)
+    }
+
+    /// Appends a record to this directory's build history.
+    pub(crate) fn append_history(&self, record: &HistoryRecord) -> Result<()> {
+        self.dest_dir
+            .append_jsonl(HistoryRecord::HISTORY_FILE_NAME, record)
+    }
+
+    /// Returns the most recent record in this directory's build history.
+    pub(crate) fn last_history(&self) -> Result<Option<HistoryRecord>> {
+        self.dest_dir
+            .read_last_jsonl(HistoryRecord::HISTORY_FILE_NAME)
+    }
+
+    /// Returns the cached toolchain version for `key`, if it was checked recently.
+    pub(crate) fn cached_toolchain_version(&self, key: &str) -> Option<String> {
+        let cache: ToolchainCache = self
+            .dest_dir
+            .read_metadata(ToolchainCache::FILE_NAME)
+            .ok()??;
+        (cache.key == key && Local::now() - cache.checked_at < ToolchainCache::MAX_AGE)
+            .then_some(cache.version)
+    }
+
+    /// Caches the toolchain version for `key`.
+    pub(crate) fn cache_toolchain_version(&self, key: String, version: String) -> Result<()> {
+        let cache = ToolchainCache {
+            key,
+            version,
+            checked_at: Local::now(),
+        };
+        self.dest_dir
+            .write_metadata(ToolchainCache::FILE_NAME, &cache)
+    }
+
+    /// Marks this directory as in use until the returned lock is dropped.
+    pub(crate) fn lock_in_use(&self) -> Result<SharedRoot<InUseLock<'_>>> {
+        UnlockedRoot::new(InUseLock(&self.dest_dir))?.lock_shared()
+    }
+
+    /// Shares this directory's registry dependency artifacts, as with [`TargoStore::share_deps`].
+    ///
+    /// Only this directory's in-use lock is taken, not the store lock, so that builds of other
+    /// workspaces aren't held up. Returns `None` if another build is using the directory.
+    pub(crate) fn share_deps(&self, registry_src: &Utf8Path) -> Result<Option<ShareDepsReport>> {
+        let Some(_in_use) = UnlockedRoot::new(InUseLock(&self.dest_dir))?.try_lock_exclusive()?
+        else {
+            return Ok(None);
+        };
+        let root_dir = self
+            .dest_dir
+            .path()
+            .parent()
+            .expect("managed directory is within a root");
+        let mut report = ShareDepsReport::default();
+        let mut sharer = DepsSharer::new(root_dir, registry_src, false, &mut report)?;
+        sharer.share(&self.dest_dir.path().join("target"))?;
+        Ok(Some(report))
+    }
+
+    fn new_symlinked(root: &StoreRoot, source_link: &Utf8Path, encoded: &str) -> Result<Self> {
+        Self::new(root, encoded, Placement::Symlink, |metadata| {
+            // TODO: check existing backlinks
+            metadata.backlinks.insert(source_link.to_owned());
+        })
+    }
+
+    fn new(
+        root: &StoreRoot,
+        encoded: &str,
+        placement: Placement,
+        update_metadata: impl FnOnce(&mut TargetDirMetadata),
+    ) -> Result<Self> {
+        // Create the directory if it doesn't exist. For volatile roots, the directory may have
+        // vanished (e.g. after a reboot), so restore it from a snapshot if possible.
+        let dest_dir_path = root.dir.path().join(encoded);
         let target_dir = dest_dir_path.join("target");
-        store
-            .store_dir
+        if !root.dir.dir().is_dir(encoded) {
+            root.restore_snapshot(encoded)?;
+        }
+        root.dir
             .dir()
             .create_dir_all(Utf8Path::new(encoded).join("target"))
             .wrap_err_with(|| {
                 format!("failed to create managed target directory `{dest_dir_path}`")
             })?;
-        let dest_dir = store.store_dir.dir().open_dir(encoded).wrap_err_with(|| {
-            format!("failed to open managed target directory `{dest_dir_path}`")
-        })?;
-        let dest_dir = DirWithPath::new(dest_dir, dest_dir_path);
+        let dest_dir = root.open_managed(encoded)?;
 
         let mut metadata =
             Self::read_dir_metadata(&dest_dir)?.unwrap_or_else(TargetDirMetadata::new);
-        // TODO: check existing backlinks
-        metadata.backlinks.insert(source_link.clone());
+        update_metadata(&mut metadata);
         metadata.update_last_used();
 
         Self::write_dir_metadata(&dest_dir, &metadata)?;
+        root.evict_if_low_on_space(encoded)?;
 
         Ok(Self {
-            source_link,
             dest_dir,
             target_dir,
+            placement,
         })
     }
 
-    fn read_dir_metadata(dest_dir: &DirWithPath) -> Result<Option<TargetDirMetadata>> {
+    pub(crate) fn read_dir_metadata(dest_dir: &DirWithPath) -> Result<Option<TargetDirMetadata>> {
         dest_dir.read_metadata(TargetDirMetadata::METADATA_FILE_NAME)
     }
 
@@ -252,6 +1414,25 @@ impl ManagedTargetDir {
     }
 }
 
+/// Returns the encoded name of the managed directory for a workspace's target directory.
+///
+/// The usual `target` directory is keyed by the workspace path alone. Other target directories
+/// within the workspace (e.g. adopted with `targo adopt --target-dir-name`) get managed
+/// directories of their own, keyed by the workspace and the name. These keys don't start with
+/// `/`, so they can't collide with the key of a workspace nested at the same path.
+pub(crate) fn target_dir_key(workspace_dir: &Utf8Path, target_dir: &Utf8Path) -> String {
+    if target_dir == workspace_dir.join("target") {
+        return encode_workspace_path(workspace_dir);
+    }
+    let name = target_dir
+        .strip_prefix(workspace_dir)
+        .unwrap_or(target_dir)
+        .as_str();
+    // The name's length is included so that the key is unambiguous even if the workspace path
+    // or the name contains `:`.
+    encode_key(&format!("target-dir:{}:{name}:{workspace_dir}", name.len()))
+}
+
 fn get_encoded_workspace<'b>(store_dir: &Utf8Path, path: &'b Utf8Path) -> Option<&'b str> {
     // Don't touch relative symlinks.
     if !path.is_absolute() {
@@ -302,9 +1483,17 @@ const HASH_SUFFIX_LEN: usize = 8;
 /// - `/path_with_underscore` → `_spath__with__underscore`
 /// - `/weird*path?` → `_sweird_apath_m`
 fn encode_workspace_path(path: &Utf8Path) -> String {
-    let mut encoded = String::with_capacity(path.as_str().len() * 2);
+    encode_key(path.as_str())
+}
+
+/// Encodes an arbitrary key into a directory-safe string.
+///
+/// This uses the same scheme as [`encode_workspace_path`]. Keys that aren't workspace paths
+/// don't start with `/`, so they can't collide with encoded (absolute) workspace paths.
+fn encode_key(key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len() * 2);
 
-    for ch in path.as_str().chars() {
+    for ch in key.chars() {
         match ch {
             '_' => encoded.push_str("__"),
             '/' => encoded.push_str("_s"),
@@ -379,6 +1568,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_target_dir_key() {
+        let workspace = Utf8Path::new("/a");
+        assert_eq!(
+            target_dir_key(workspace, Utf8Path::new("/a/target")),
+            "_sa",
+            "the usual target directory is keyed by the workspace"
+        );
+        assert_eq!(
+            target_dir_key(workspace, Utf8Path::new("/a/sub")),
+            "target-dir_c3_csub_c_sa"
+        );
+        // A workspace nested at the same path as a custom target directory.
+        assert_ne!(
+            target_dir_key(workspace, Utf8Path::new("/a/sub")),
+            target_dir_key(Utf8Path::new("/a/sub"), Utf8Path::new("/a/sub/target")),
+        );
+        assert_ne!(
+            target_dir_key(Utf8Path::new("/a"), Utf8Path::new("/a/b:c")),
+            target_dir_key(Utf8Path::new("/a/b"), Utf8Path::new("/a/b/c")),
+        );
+    }
+
     // Basic encoding tests.
     #[test]
     fn test_encode_workspace_path() {
@@ -0,0 +1,118 @@
+//! Tests for `targo adopt`.
+
+mod common;
+
+use common::Fixture;
+use std::fs;
+
+#[test]
+fn test_adopt() {
+    let fixture = Fixture::new("", "exit 0");
+    let target_dir = fixture.workspace().join("target");
+    fs::create_dir(&target_dir).unwrap();
+    fs::write(target_dir.join("output"), "built\n").unwrap();
+
+    let output = fixture
+        .targo_command()
+        .args(["adopt", "--dry-run", "--message-format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "dry run succeeded");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["workspaces"][0]["action"], "replace-directory");
+    let metadata = fs::symlink_metadata(&target_dir).unwrap();
+    assert!(metadata.is_dir(), "dry run left target alone");
+
+    let status = fixture.targo_command().arg("adopt").status().unwrap();
+    assert!(status.success(), "adopt succeeded");
+    assert!(target_dir.is_symlink(), "target is a symlink after adopt");
+}
+
+#[test]
+fn test_adopt_after_eject() {
+    let fixture = Fixture::new("", "exit 0");
+    let target_dir = fixture.workspace().join("target");
+
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let status = fixture.targo_command().arg("eject").status().unwrap();
+    assert!(status.success(), "eject succeeded");
+    assert!(!target_dir.is_symlink(), "target is a real directory");
+
+    let status = fixture.targo_command().arg("adopt").status().unwrap();
+    assert!(status.success(), "adopt succeeded");
+    assert!(target_dir.is_symlink(), "target is managed again");
+}
+
+#[test]
+fn test_adopt_target_dir_name() {
+    let fixture = Fixture::new("", "exit 0");
+
+    let status = fixture.targo_command().arg("adopt").status().unwrap();
+    assert!(status.success(), "adopt succeeded");
+    let status = fixture
+        .targo_command()
+        .args(["adopt", "--target-dir-name", "target-ra"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "adopt with a custom name succeeded");
+
+    let target = fs::read_link(fixture.workspace().join("target")).unwrap();
+    let target_ra = fs::read_link(fixture.workspace().join("target-ra")).unwrap();
+    assert_ne!(
+        target, target_ra,
+        "each target directory is managed separately"
+    );
+
+    for name in ["/tmp/target", "../outside", "sub/../../outside", ".", ""] {
+        let status = fixture
+            .targo_command()
+            .args(["adopt", "--target-dir-name", name])
+            .status()
+            .unwrap();
+        assert!(
+            !status.success(),
+            "target directory name `{name}` is rejected"
+        );
+    }
+    assert!(
+        !fixture
+            .workspace()
+            .parent()
+            .unwrap()
+            .join("outside")
+            .exists(),
+        "nothing was created outside the workspace"
+    );
+}
+
+#[test]
+fn test_adopt_target_dir_name_nested_workspace() {
+    let fixture = Fixture::new("", "exit 0");
+    let nested = fixture.workspace().join("sub");
+
+    let status = fixture
+        .targo_command()
+        .args(["adopt", "--target-dir-name", "sub"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "adopt with a custom name succeeded");
+    let custom = fs::read_link(&nested).unwrap();
+
+    // Replace the custom target directory with a workspace at the same path.
+    fs::remove_file(&nested).unwrap();
+    fs::create_dir(&nested).unwrap();
+    fs::write(nested.join("Cargo.toml"), "").unwrap();
+    let status = fixture
+        .targo_command()
+        .arg("adopt")
+        .arg(&nested)
+        .status()
+        .unwrap();
+    assert!(status.success(), "adopt of the nested workspace succeeded");
+
+    let nested_target = fs::read_link(nested.join("target")).unwrap();
+    assert_ne!(
+        custom, nested_target,
+        "the nested workspace has its own managed directory"
+    );
+}
@@ -0,0 +1,36 @@
+//! Tests for running targo as the `cargo targo` external subcommand.
+
+mod common;
+
+use common::Fixture;
+use std::{fs, os::unix::fs::symlink};
+
+#[test]
+fn test_cargo_subcommand() {
+    let fixture = Fixture::new("", "exit 0");
+    let bin_dir = fixture.store_dir().join("bin");
+    fs::create_dir_all(&bin_dir).unwrap();
+    let cargo_targo = bin_dir.join("cargo-targo");
+    symlink(env!("CARGO_BIN_EXE_targo"), &cargo_targo).unwrap();
+
+    // Cargo runs `cargo-targo targo <args>` for `cargo targo <args>`.
+    let output = fixture
+        .command(&cargo_targo)
+        .args(["targo", "status", "--message-format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "status succeeded");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["action"], "create-symlink", "status was reported");
+
+    let output = fixture
+        .command(&cargo_targo)
+        .args(["targo", "--help"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Usage: cargo targo"),
+        "help uses the subcommand name: {stdout}"
+    );
+}
@@ -0,0 +1,175 @@
+//! Shared helpers for integration tests, using a fake `CARGO` binary.
+
+// Not every test binary uses every helper.
+#![allow(dead_code)]
+
+use chrono::{Local, TimeDelta};
+use fs2::FileExt;
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use tempfile::TempDir;
+
+pub struct Fixture {
+    dir: TempDir,
+}
+
+impl Fixture {
+    /// Sets up a workspace, a store with the given config, and a fake Cargo that runs `script`
+    /// for every command other than `locate-project`.
+    pub fn new(config: &str, script: &str) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let fixture = Self { dir };
+
+        fs::create_dir_all(fixture.workspace()).unwrap();
+        fs::write(fixture.workspace().join("Cargo.toml"), "").unwrap();
+        fs::create_dir_all(fixture.store_dir()).unwrap();
+        fs::write(fixture.store_dir().join("config.toml"), config).unwrap();
+
+        let fake_cargo = fixture.fake_cargo();
+        fs::write(
+            &fake_cargo,
+            format!(
+                "#!/bin/sh\n\
+                 if [ \"$1\" = locate-project ]; then echo \"$(pwd -P)/Cargo.toml\"; exit 0; fi\n\
+                 {script}\n"
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&fake_cargo, fs::Permissions::from_mode(0o755)).unwrap();
+
+        fixture
+    }
+
+    /// Replaces the store's config.
+    pub fn write_config(&self, config: &str) {
+        fs::write(self.store_dir().join("config.toml"), config).unwrap();
+    }
+
+    /// Returns a path within the fixture's temporary directory, e.g. for an extra store root.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    pub fn workspace(&self) -> PathBuf {
+        self.dir.path().join("workspace")
+    }
+
+    pub fn store_dir(&self) -> PathBuf {
+        self.dir.path().join("cargo-home").join("targo")
+    }
+
+    pub fn fake_cargo(&self) -> PathBuf {
+        self.dir.path().join("fake-cargo")
+    }
+
+    pub fn ready_file(&self) -> PathBuf {
+        self.dir.path().join("ready")
+    }
+
+    pub fn targo_command(&self) -> Command {
+        self.command(env!("CARGO_BIN_EXE_targo"))
+    }
+
+    /// Returns a command for `program` with the same environment as [`Self::targo_command`].
+    pub fn command(&self, program: impl AsRef<std::ffi::OsStr>) -> Command {
+        let mut command = Command::new(program);
+        command
+            .current_dir(self.workspace())
+            .env("CARGO", self.fake_cargo())
+            .env("CARGO_HOME", self.dir.path().join("cargo-home"))
+            .env("READY_FILE", self.ready_file())
+            .env_remove("CARGO_TARGET_DIR")
+            .env_remove("TARGO_ACTIVE")
+            .stdin(Stdio::null());
+        command
+    }
+
+    pub fn spawn_targo(&self) -> Child {
+        self.targo_command()
+            .args(["wrap-cargo", "build"])
+            .spawn()
+            .unwrap()
+    }
+
+    pub fn run_targo(&self) -> ExitStatus {
+        self.spawn_targo().wait().unwrap()
+    }
+
+    /// Waits for the fake Cargo to signal that it's running.
+    pub fn wait_until_ready(&self) {
+        let start = Instant::now();
+        while !self.ready_file().exists() {
+            assert!(
+                start.elapsed() < Duration::from_secs(30),
+                "timed out waiting for fake cargo to start"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Makes the workspace read-only. Returns false if it's still writable, e.g. because the
+    /// tests are running as root.
+    pub fn make_workspace_read_only(&self) -> bool {
+        fs::set_permissions(self.workspace(), fs::Permissions::from_mode(0o555)).unwrap();
+        let probe = self.workspace().join(".probe");
+        match fs::write(&probe, "") {
+            Ok(()) => {
+                fs::remove_file(&probe).unwrap();
+                false
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Returns whether the target directory is marked as in use by a build.
+    pub fn is_in_use(&self) -> bool {
+        let lock = find_in_use_lock(&self.store_dir()).expect("in-use lock exists");
+        let file = fs::File::open(lock).unwrap();
+        match file.try_lock_exclusive() {
+            Ok(()) => false,
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => true,
+            Err(err) => panic!("failed to lock: {err}"),
+        }
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        // Make sure the temporary directory can be cleaned up.
+        _ = fs::set_permissions(self.workspace(), fs::Permissions::from_mode(0o755));
+    }
+}
+
+/// Returns the managed directories in a store root, sorted by name.
+pub fn managed_dirs(store_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<_> = fs::read_dir(store_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.join("target-dir-metadata.json").exists())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// Rewrites a managed directory's metadata so that it was last used `days` days ago.
+pub fn set_last_used_days_ago(managed_dir: &Path, days: i64) {
+    let path = managed_dir.join("target-dir-metadata.json");
+    let mut metadata: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    let last_used = Local::now() - TimeDelta::days(days);
+    metadata["last-used"] = last_used.to_rfc3339().into();
+    fs::write(&path, metadata.to_string()).unwrap();
+}
+
+pub fn find_in_use_lock(store_dir: &Path) -> Option<PathBuf> {
+    fs::read_dir(store_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path().join("targo-in-use.lock"))
+        .find(|path| path.exists())
+}
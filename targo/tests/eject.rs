@@ -0,0 +1,89 @@
+//! Tests for `targo eject`.
+
+mod common;
+
+use common::{managed_dirs, Fixture};
+use std::fs;
+
+#[test]
+fn test_eject() {
+    let fixture = Fixture::new("", "echo built > target/output");
+    let target_dir = fixture.workspace().join("target");
+
+    assert!(fixture.run_targo().success(), "build succeeded");
+    assert!(target_dir.is_symlink(), "target is a symlink after build");
+
+    let status = fixture.targo_command().arg("eject").status().unwrap();
+    assert!(status.success(), "eject succeeded");
+    let metadata = fs::symlink_metadata(&target_dir).unwrap();
+    assert!(metadata.is_dir(), "target is a real directory after eject");
+    assert_eq!(
+        fs::read_to_string(target_dir.join("output")).unwrap(),
+        "built\n",
+        "build output was moved back"
+    );
+
+    // The workspace is left alone from now on.
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let metadata = fs::symlink_metadata(&target_dir).unwrap();
+    assert!(metadata.is_dir(), "target is still a real directory");
+
+    let output = fixture
+        .targo_command()
+        .args(["status", "--message-format", "json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["opted-out"], true, "status reports the opt-out");
+    assert_eq!(json["action"], "opted-out", "status reports no action");
+}
+
+#[test]
+fn test_eject_foreign_symlink() {
+    let fixture = Fixture::new("", "exit 0");
+    let elsewhere = fixture.workspace().join("elsewhere");
+    fs::create_dir(&elsewhere).unwrap();
+    std::os::unix::fs::symlink(&elsewhere, fixture.workspace().join("target")).unwrap();
+
+    let status = fixture.targo_command().arg("eject").status().unwrap();
+    assert!(!status.success(), "ejecting a foreign symlink fails");
+}
+
+#[test]
+fn test_eject_env_placement() {
+    let fixture = Fixture::new(
+        "placement = \"env\"\n",
+        "mkdir -p \"$CARGO_TARGET_DIR\" && echo built > \"$CARGO_TARGET_DIR/output\"",
+    );
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let target_dir = fixture.workspace().join("target");
+    assert!(!target_dir.exists(), "the workspace is left untouched");
+    let dirs = managed_dirs(&fixture.store_dir());
+    assert_eq!(dirs.len(), 1, "a managed directory was created");
+
+    let status = fixture.targo_command().arg("eject").status().unwrap();
+    assert!(status.success(), "eject succeeded");
+    let metadata = fs::symlink_metadata(&target_dir).unwrap();
+    assert!(metadata.is_dir(), "target is a real directory after eject");
+    assert_eq!(
+        fs::read_to_string(target_dir.join("output")).unwrap(),
+        "built\n",
+        "build output was moved back"
+    );
+    assert!(!dirs[0].exists(), "the managed directory was removed");
+}
+
+#[test]
+fn test_eject_env_placement_existing_target() {
+    let fixture = Fixture::new("placement = \"env\"\n", "exit 0");
+    assert!(fixture.run_targo().success(), "build succeeded");
+    fs::create_dir(fixture.workspace().join("target")).unwrap();
+
+    let status = fixture.targo_command().arg("eject").status().unwrap();
+    assert!(!status.success(), "eject refuses to overwrite `target`");
+    assert_eq!(
+        managed_dirs(&fixture.store_dir()).len(),
+        1,
+        "the managed directory is kept"
+    );
+}
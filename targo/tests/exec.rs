@@ -0,0 +1,59 @@
+//! Tests for `targo exec`.
+
+mod common;
+
+use common::Fixture;
+use std::fs;
+
+#[test]
+fn test_exec() {
+    let fixture = Fixture::new("", "exit 0");
+    let status = fixture
+        .targo_command()
+        .args([
+            "exec",
+            "--",
+            "sh",
+            "-c",
+            "echo \"$CARGO_TARGET_DIR\" > dir; echo \"$TARGO_WORKSPACE\" > workspace",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success(), "exec succeeded");
+
+    let target_dir = fixture.workspace().join("target");
+    assert!(
+        target_dir.is_symlink(),
+        "target was set up before the command ran"
+    );
+    let dir = fs::read_to_string(fixture.workspace().join("dir")).unwrap();
+    assert_eq!(
+        dir.trim_end(),
+        fs::read_link(&target_dir).unwrap().to_str().unwrap(),
+        "CARGO_TARGET_DIR is the managed directory"
+    );
+    let workspace = fs::read_to_string(fixture.workspace().join("workspace")).unwrap();
+    assert_eq!(
+        workspace.trim_end(),
+        fs::canonicalize(fixture.workspace())
+            .unwrap()
+            .to_str()
+            .unwrap(),
+        "TARGO_WORKSPACE is the workspace root"
+    );
+}
+
+#[test]
+fn test_exec_exit_code() {
+    let fixture = Fixture::new("run-mode = \"spawn\"", "exit 0");
+    let status = fixture
+        .targo_command()
+        .args(["exec", "--", "sh", "-c", "exit 3"])
+        .status()
+        .unwrap();
+    assert_eq!(
+        status.code(),
+        Some(3),
+        "the command's exit code is propagated"
+    );
+}
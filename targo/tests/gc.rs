@@ -0,0 +1,139 @@
+//! Tests for `targo gc`.
+
+mod common;
+
+use common::{managed_dirs, set_last_used_days_ago, Fixture};
+use fs2::FileExt;
+use std::fs;
+
+#[test]
+fn test_gc_by_age() {
+    let fixture = Fixture::new("", "exit 0");
+    let other = fixture.workspace().join("other");
+    fs::create_dir(&other).unwrap();
+    fs::write(other.join("Cargo.toml"), "").unwrap();
+
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let status = fixture
+        .targo_command()
+        .current_dir(&other)
+        .args(["wrap-cargo", "build"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "build succeeded");
+
+    let target_dir = fixture.workspace().join("target");
+    let old_dir = fs::read_link(&target_dir).unwrap();
+    let old_dir = old_dir.parent().unwrap();
+    set_last_used_days_ago(old_dir, 31);
+
+    let status = fixture.targo_command().arg("gc").status().unwrap();
+    assert!(status.success(), "gc succeeded");
+    assert!(
+        !old_dir.exists(),
+        "directory unused for 31 days was removed"
+    );
+    assert!(
+        !target_dir.is_symlink(),
+        "the symlink to the removed directory was removed"
+    );
+    assert!(
+        other.join("target").exists(),
+        "recently used directory was kept"
+    );
+
+    // The max age can be overridden on the command line.
+    let status = fixture
+        .targo_command()
+        .args(["gc", "--max-age-days", "0"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "gc succeeded");
+    assert!(
+        managed_dirs(&fixture.store_dir()).is_empty(),
+        "all directories were removed"
+    );
+}
+
+#[test]
+fn test_gc_dry_run() {
+    let fixture = Fixture::new("", "echo built > target/output");
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let dirs = managed_dirs(&fixture.store_dir());
+    set_last_used_days_ago(&dirs[0], 31);
+
+    let output = fixture
+        .targo_command()
+        .args(["gc", "--dry-run", "--message-format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "gc succeeded");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["dry-run"], true, "report is for a dry run");
+    let removed = json["removed"].as_array().unwrap();
+    assert_eq!(removed.len(), 1, "the old directory would be removed");
+    assert_eq!(removed[0]["path"], dirs[0].to_str().unwrap());
+    assert!(
+        json["total-size"].as_u64().unwrap() >= 6,
+        "the size of the output is included"
+    );
+
+    assert!(dirs[0].exists(), "nothing was removed");
+    assert!(
+        fixture.workspace().join("target/output").exists(),
+        "the symlink still works"
+    );
+}
+
+#[test]
+fn test_gc_skips_in_use() {
+    let fixture = Fixture::new("", "exit 0");
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let dirs = managed_dirs(&fixture.store_dir());
+    set_last_used_days_ago(&dirs[0], 31);
+
+    // Mark the directory as in use, the same way a build in spawn mode does.
+    let lock = fs::File::create(dirs[0].join("targo-in-use.lock")).unwrap();
+    FileExt::lock_shared(&lock).unwrap();
+
+    let status = fixture.targo_command().arg("gc").status().unwrap();
+    assert!(status.success(), "gc succeeded");
+    assert!(dirs[0].exists(), "directory in use was kept");
+
+    FileExt::unlock(&lock).unwrap();
+    let status = fixture.targo_command().arg("gc").status().unwrap();
+    assert!(status.success(), "gc succeeded");
+    assert!(
+        !dirs[0].exists(),
+        "directory was removed once no longer in use"
+    );
+}
+
+#[test]
+fn test_gc_scripts() {
+    let fixture = Fixture::new("", "exit 0");
+    fs::write(fixture.workspace().join("script.rs"), "fn main() {}\n").unwrap();
+
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let status = fixture
+        .targo_command()
+        .args(["wrap-cargo", "script.rs"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "script succeeded");
+
+    let dirs = managed_dirs(&fixture.store_dir());
+    assert_eq!(dirs.len(), 2, "the script has its own directory");
+    for dir in &dirs {
+        set_last_used_days_ago(dir, 8);
+    }
+
+    let status = fixture.targo_command().arg("gc").status().unwrap();
+    assert!(status.success(), "gc succeeded");
+    let remaining = managed_dirs(&fixture.store_dir());
+    assert_eq!(remaining.len(), 1, "the script's directory was removed");
+    assert!(
+        fixture.workspace().join("target").exists(),
+        "the workspace's directory was kept"
+    );
+}
@@ -0,0 +1,74 @@
+//! Tests for persistent target directories for `cargo install`.
+
+mod common;
+
+use common::{managed_dirs, set_last_used_days_ago, Fixture};
+use std::fs;
+
+const INSTALL_CONFIG: &str = "[install]\ncache = true\n";
+
+/// A fake Cargo that records the target directory it was pointed at, if any.
+const INSTALL_SCRIPT: &str = "echo \"$CARGO_TARGET_DIR\" > target-dir";
+
+#[test]
+fn test_install_cache() {
+    let fixture = Fixture::new(INSTALL_CONFIG, INSTALL_SCRIPT);
+    let status = fixture
+        .targo_command()
+        .args(["wrap-cargo", "install", "ripgrep@14.1.0"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "install succeeded");
+
+    let dirs = managed_dirs(&fixture.store_dir());
+    assert_eq!(dirs.len(), 1, "one managed directory was created");
+    let target_dir = fs::read_to_string(fixture.workspace().join("target-dir")).unwrap();
+    assert_eq!(
+        target_dir.trim_end(),
+        dirs[0].join("target").to_str().unwrap(),
+        "cargo was pointed at the managed directory"
+    );
+}
+
+#[test]
+fn test_install_list_not_cached() {
+    let fixture = Fixture::new(INSTALL_CONFIG, INSTALL_SCRIPT);
+    let status = fixture
+        .targo_command()
+        .args(["wrap-cargo", "install", "--list"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "install --list succeeded");
+
+    assert_eq!(
+        fs::read_to_string(fixture.workspace().join("target-dir")).unwrap(),
+        "\n",
+        "CARGO_TARGET_DIR isn't set"
+    );
+    assert!(
+        managed_dirs(&fixture.store_dir()).is_empty(),
+        "no managed directory was created"
+    );
+}
+
+#[test]
+fn test_gc_removes_old_install_dirs() {
+    let fixture = Fixture::new(INSTALL_CONFIG, INSTALL_SCRIPT);
+    let status = fixture
+        .targo_command()
+        .args(["wrap-cargo", "install", "ripgrep"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "install succeeded");
+    let dirs = managed_dirs(&fixture.store_dir());
+    assert_eq!(dirs.len(), 1, "one managed directory was created");
+
+    let status = fixture.targo_command().arg("gc").status().unwrap();
+    assert!(status.success(), "gc succeeded");
+    assert!(dirs[0].exists(), "recently used directory is kept");
+
+    set_last_used_days_ago(&dirs[0], 31);
+    let status = fixture.targo_command().arg("gc").status().unwrap();
+    assert!(status.success(), "gc succeeded");
+    assert!(!dirs[0].exists(), "directory unused for 31 days is removed");
+}
@@ -0,0 +1,66 @@
+//! Tests for `targo migrate-tree`.
+
+mod common;
+
+use common::Fixture;
+use std::fs;
+
+#[test]
+fn test_migrate_tree() {
+    let fixture = Fixture::new("", "exit 0");
+    let tree = fixture.workspace().join("tree");
+    fs::write(
+        fixture.store_dir().join("config.toml"),
+        format!(
+            "[[workspace]]\npath = {:?}\nexclude = true\n",
+            tree.join("excluded")
+        ),
+    )
+    .unwrap();
+    for name in ["one", "two", "excluded", "foreign"] {
+        fs::create_dir_all(tree.join(name)).unwrap();
+        fs::write(tree.join(name).join("Cargo.toml"), "").unwrap();
+    }
+    for name in ["one", "two", "excluded"] {
+        fs::create_dir(tree.join(name).join("target")).unwrap();
+        fs::write(tree.join(name).join("target/output"), name).unwrap();
+    }
+    fs::create_dir(tree.join("elsewhere")).unwrap();
+    std::os::unix::fs::symlink(tree.join("elsewhere"), tree.join("foreign/target")).unwrap();
+
+    let output = fixture
+        .targo_command()
+        .args(["migrate-tree", "tree", "--message-format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "migrate-tree succeeded");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        json["migrated"].as_array().unwrap().len(),
+        2,
+        "two migrated"
+    );
+    assert_eq!(
+        json["total-size"], 6,
+        "sizes of moved directories are reported"
+    );
+    let reasons: Vec<_> = json["skipped"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|skipped| skipped["reason"].as_str().unwrap())
+        .collect();
+    assert_eq!(reasons, ["excluded", "foreign"], "skip reasons match");
+
+    for name in ["one", "two"] {
+        let target_dir = tree.join(name).join("target");
+        assert!(target_dir.is_symlink(), "{name}'s target is a symlink");
+        assert_eq!(
+            fs::read_to_string(target_dir.join("output")).unwrap(),
+            name,
+            "{name}'s build output was moved into the store"
+        );
+    }
+    let metadata = fs::symlink_metadata(tree.join("excluded/target")).unwrap();
+    assert!(metadata.is_dir(), "excluded workspace is left alone");
+}
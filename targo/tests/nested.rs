@@ -0,0 +1,98 @@
+//! Tests for targo running within a Cargo command that targo started.
+
+mod common;
+
+use common::Fixture;
+use std::fs;
+
+#[test]
+fn test_marks_child_as_active() {
+    let fixture = Fixture::new("", "echo \"$TARGO_ACTIVE\" > active");
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let workspace = fs::canonicalize(fixture.workspace()).unwrap();
+    assert_eq!(
+        fs::read_to_string(fixture.workspace().join("active")).unwrap(),
+        format!("{}\n", workspace.display()),
+        "TARGO_ACTIVE is set to the workspace for Cargo"
+    );
+}
+
+#[test]
+fn test_nested_passes_through() {
+    let fixture = Fixture::new("", "echo ran > ran");
+    let workspace = fs::canonicalize(fixture.workspace()).unwrap();
+    let status = fixture
+        .targo_command()
+        .args(["wrap-cargo", "build"])
+        .env("TARGO_ACTIVE", &workspace)
+        .status()
+        .unwrap();
+    assert!(status.success(), "nested build succeeded");
+    assert!(
+        fixture.workspace().join("ran").exists(),
+        "cargo was run directly"
+    );
+    assert!(
+        !fixture.workspace().join("target").exists(),
+        "the store wasn't touched"
+    );
+}
+
+#[test]
+fn test_nested_skips_config() {
+    let fixture = Fixture::new("", "echo ran > ran");
+    fixture.write_config("not valid = [");
+    let workspace = fs::canonicalize(fixture.workspace()).unwrap();
+    let subdir = fixture.workspace().join("src");
+    fs::create_dir(&subdir).unwrap();
+    let status = fixture
+        .targo_command()
+        .current_dir(&subdir)
+        .args(["wrap-cargo", "metadata"])
+        .env("TARGO_ACTIVE", &workspace)
+        .status()
+        .unwrap();
+    assert!(status.success(), "nested call succeeded despite the config");
+    assert!(subdir.join("ran").exists(), "cargo was run directly");
+}
+
+#[test]
+fn test_nested_in_other_workspace() {
+    let fixture = Fixture::new("", "echo \"$TARGO_ACTIVE\" > active");
+    let other = fixture.path("other");
+    fs::create_dir(&other).unwrap();
+    let status = fixture
+        .targo_command()
+        .args(["wrap-cargo", "build"])
+        .env("TARGO_ACTIVE", &other)
+        .status()
+        .unwrap();
+    assert!(status.success(), "nested build succeeded");
+    assert!(
+        fixture.workspace().join("target").is_symlink(),
+        "the workspace's target directory is managed"
+    );
+    let workspace = fs::canonicalize(fixture.workspace()).unwrap();
+    assert_eq!(
+        fs::read_to_string(fixture.workspace().join("active")).unwrap(),
+        format!("{}\n", workspace.display()),
+        "TARGO_ACTIVE is set to this workspace for Cargo"
+    );
+}
+
+#[test]
+fn test_refuses_self_referential_cargo() {
+    let fixture = Fixture::new("", "exit 0");
+    let output = fixture
+        .targo_command()
+        .args(["wrap-cargo", "build"])
+        .env("CARGO", env!("CARGO_BIN_EXE_targo"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "targo refuses to run itself");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("which is targo itself"),
+        "error explains the problem: {stderr}"
+    );
+}
@@ -0,0 +1,63 @@
+//! Tests for `targo ra-target-dir`.
+
+mod common;
+
+use common::Fixture;
+use std::{fs, path::PathBuf};
+
+#[test]
+fn test_ra_target_dir() {
+    let fixture = Fixture::new("", "exit 0");
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let output = fixture
+        .targo_command()
+        .arg("ra-target-dir")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "ra-target-dir succeeded");
+    let ra_dir = PathBuf::from(String::from_utf8(output.stdout).unwrap().trim_end());
+    assert!(ra_dir.is_dir(), "the directory was created");
+
+    let target_dir = fs::read_link(fixture.workspace().join("target")).unwrap();
+    assert_eq!(
+        ra_dir.parent(),
+        target_dir.parent(),
+        "the directory is next to the managed target directory"
+    );
+    assert_ne!(ra_dir, target_dir, "the directories are separate");
+
+    let status = fixture
+        .targo_command()
+        .args(["gc", "--max-age-days", "0"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "gc succeeded");
+    assert!(
+        !ra_dir.exists(),
+        "gc removed the directory with the workspace's"
+    );
+}
+
+#[test]
+fn test_ra_target_dir_unmanaged() {
+    let fixture = Fixture::new("", "exit 0");
+    let target_dir = fixture.workspace().join("target");
+    fs::create_dir(&target_dir).unwrap();
+    fs::write(target_dir.join("build-output"), "keep").unwrap();
+
+    let output = fixture
+        .targo_command()
+        .arg("ra-target-dir")
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "ra-target-dir failed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("run a build with targo"),
+        "error suggests running a build: {stderr}"
+    );
+    assert!(
+        target_dir.join("build-output").exists(),
+        "the existing target directory was left alone"
+    );
+}
@@ -0,0 +1,40 @@
+//! Tests for workspaces that targo can't create a symlink in.
+
+mod common;
+
+use common::{managed_dirs, Fixture};
+use std::fs;
+
+#[test]
+fn test_read_only_workspace_uses_env() {
+    let fixture = Fixture::new("", "echo \"$CARGO_TARGET_DIR\" > \"$READY_FILE\"");
+    if !fixture.make_workspace_read_only() {
+        eprintln!("skipping test: the workspace is still writable (running as root?)");
+        return;
+    }
+
+    let output = fixture
+        .targo_command()
+        .args(["wrap-cargo", "build"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "build succeeded");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("is not writable, setting CARGO_TARGET_DIR instead"),
+        "the fallback is reported: {stderr}"
+    );
+
+    let dirs = managed_dirs(&fixture.store_dir());
+    assert_eq!(dirs.len(), 1, "a managed directory was created");
+    assert_eq!(
+        fs::read_to_string(fixture.ready_file()).unwrap().trim_end(),
+        dirs[0].join("target").to_str().unwrap(),
+        "cargo was pointed at the managed directory"
+    );
+    let entries: Vec<_> = fs::read_dir(fixture.workspace())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(entries, ["Cargo.toml"], "the workspace wasn't changed");
+}
@@ -0,0 +1,61 @@
+//! Tests for placing new managed directories according to root rules.
+
+mod common;
+
+use common::Fixture;
+use std::fs;
+
+#[test]
+fn test_size_rule_on_discard() {
+    let fixture = Fixture::new("", "mkdir -p target && echo built > target/output");
+    let big_root = fixture.path("big-root");
+    fixture.write_config(&format!(
+        "[[root]]\nname = \"big\"\npath = \"{}\"\n\n\
+         [[root-rule]]\nmin-size-gib = 1\nroot = \"big\"\n",
+        big_root.display()
+    ));
+
+    // A sparse file is enough, since sizes are based on file lengths.
+    let target_dir = fixture.workspace().join("target");
+    fs::create_dir(&target_dir).unwrap();
+    let file = fs::File::create(target_dir.join("large")).unwrap();
+    file.set_len(1024 * 1024 * 1024).unwrap();
+
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let dest = fs::read_link(&target_dir).unwrap();
+    assert!(
+        dest.starts_with(&big_root),
+        "the size of the discarded target directory chose the root"
+    );
+    assert!(
+        !target_dir.join("large").exists(),
+        "the existing target directory was discarded"
+    );
+
+    // A clean rebuild keeps using the same root, even though it's now small.
+    fs::remove_dir_all(&dest).unwrap();
+    assert!(fixture.run_targo().success(), "rebuild succeeded");
+    assert_eq!(
+        fs::read_link(&target_dir).unwrap(),
+        dest,
+        "the managed directory stayed in its root"
+    );
+
+    // A workspace without a target directory counts as empty.
+    let other = fixture.workspace().join("other");
+    fs::create_dir(&other).unwrap();
+    fs::write(other.join("Cargo.toml"), "").unwrap();
+    let status = fixture
+        .targo_command()
+        .current_dir(&other)
+        .args(["wrap-cargo", "build"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "build of the other workspace succeeded");
+    assert!(
+        fs::read_link(other.join("target"))
+            .unwrap()
+            .starts_with(fixture.store_dir()),
+        "a new workspace is placed in the default root"
+    );
+}
@@ -0,0 +1,126 @@
+//! Tests for sharing dependency artifacts between managed directories.
+
+mod common;
+
+use common::Fixture;
+use std::{fs, os::unix::fs::MetadataExt, path::Path};
+
+/// A fake build that produces one registry artifact and one workspace artifact.
+const BUILD_SCRIPT: &str = r#"
+deps=target/debug/deps
+fp=target/debug/.fingerprint
+mkdir -p "$fp/serde-0123456789abcdef" "$fp/local-0123456789abcdef" "$deps"
+echo 1111222233334444 > "$fp/serde-0123456789abcdef/lib-serde"
+echo 5555666677778888 > "$fp/local-0123456789abcdef/lib-local"
+src="$CARGO_HOME/registry/src/index/serde-1.0.0/src/lib.rs"
+echo serde > "$deps/libserde-0123456789abcdef.rlib"
+echo "$deps/libserde-0123456789abcdef.rlib: $src" > "$deps/serde-0123456789abcdef.d"
+pwd > "$deps/liblocal-0123456789abcdef.rlib"
+echo "$deps/liblocal-0123456789abcdef.rlib: $(pwd)/src/lib.rs" > "$deps/local-0123456789abcdef.d"
+"#;
+
+fn inode(path: &Path) -> u64 {
+    fs::metadata(path).unwrap().ino()
+}
+
+#[test]
+fn test_share_deps() {
+    let fixture = Fixture::new("[shared-deps]\nenabled = true\n", BUILD_SCRIPT);
+    let other = fixture.workspace().join("other");
+    fs::create_dir(&other).unwrap();
+    fs::write(other.join("Cargo.toml"), "").unwrap();
+
+    assert!(fixture.run_targo().success(), "first build succeeded");
+    let status = fixture
+        .targo_command()
+        .current_dir(&other)
+        .args(["wrap-cargo", "build"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "second build succeeded");
+
+    let output = fixture
+        .targo_command()
+        .args(["share-deps", "--message-format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "share-deps succeeded");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["scanned"], 2, "only registry artifacts are scanned");
+    assert_eq!(json["stored"], 1, "one copy is stored");
+    assert_eq!(json["linked"], 1, "the other is linked to it");
+    assert_eq!(json["saved-size"], 6, "the duplicate's size is saved");
+
+    let artifact = "target/debug/deps/libserde-0123456789abcdef.rlib";
+    assert_eq!(
+        inode(&fixture.workspace().join(artifact)),
+        inode(&other.join(artifact)),
+        "registry artifacts are the same file"
+    );
+    let local = "target/debug/deps/liblocal-0123456789abcdef.rlib";
+    assert_ne!(
+        inode(&fixture.workspace().join(local)),
+        inode(&other.join(local)),
+        "workspace artifacts aren't shared"
+    );
+
+    // Once both managed directories are gone, gc removes the shared artifact too.
+    let output = fixture
+        .targo_command()
+        .args(["gc", "--max-age-days", "0", "--message-format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "gc succeeded");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        json["shared-deps-size"], 6,
+        "the shared artifact was removed"
+    );
+}
+
+#[test]
+fn test_share_deps_after_build() {
+    let fixture = Fixture::new(
+        "run-mode = \"spawn\"\n[shared-deps]\nenabled = true\nafter-build = true\n",
+        BUILD_SCRIPT,
+    );
+    let other = fixture.workspace().join("other");
+    fs::create_dir(&other).unwrap();
+    fs::write(other.join("Cargo.toml"), "").unwrap();
+
+    assert!(fixture.run_targo().success(), "first build succeeded");
+    let status = fixture
+        .targo_command()
+        .current_dir(&other)
+        .args(["wrap-cargo", "build"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "second build succeeded");
+
+    let artifact = "target/debug/deps/libserde-0123456789abcdef.rlib";
+    assert_eq!(
+        inode(&fixture.workspace().join(artifact)),
+        inode(&other.join(artifact)),
+        "registry artifacts were shared after each build"
+    );
+
+    let output = fixture
+        .targo_command()
+        .args(["share-deps", "--message-format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "share-deps succeeded");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        json["already-shared"], 2,
+        "both artifacts are already shared"
+    );
+    assert_eq!(json["stored"], 0, "nothing more is stored");
+}
+
+#[test]
+fn test_share_deps_disabled() {
+    let fixture = Fixture::new("", "exit 0");
+    let status = fixture.targo_command().arg("share-deps").status().unwrap();
+    assert!(!status.success(), "share-deps requires opting in");
+}
@@ -0,0 +1,70 @@
+//! Tests for running targo as a `cargo` shim.
+
+mod common;
+
+use common::Fixture;
+use std::{env, fs, os::unix::fs::symlink, path::Path};
+
+/// Returns a `PATH` with the given directories in front of the current one.
+fn path_with(dirs: &[&Path]) -> std::ffi::OsString {
+    let current = env::var_os("PATH").unwrap_or_default();
+    let dirs = dirs
+        .iter()
+        .map(|dir| dir.to_path_buf())
+        .chain(env::split_paths(&current));
+    env::join_paths(dirs).unwrap()
+}
+
+#[test]
+fn test_shim() {
+    let fixture = Fixture::new("", "echo built > target/output");
+    let shim_dir = fixture.store_dir().join("shim-bin");
+    let real_dir = fixture.store_dir().join("real-bin");
+    fs::create_dir_all(&shim_dir).unwrap();
+    fs::create_dir_all(&real_dir).unwrap();
+    symlink(env!("CARGO_BIN_EXE_targo"), shim_dir.join("cargo")).unwrap();
+    fs::copy(fixture.fake_cargo(), real_dir.join("cargo")).unwrap();
+
+    let status = fixture
+        .command(shim_dir.join("cargo"))
+        .arg("build")
+        .env_remove("CARGO")
+        .env("PATH", path_with(&[&shim_dir, &real_dir]))
+        .status()
+        .unwrap();
+    assert!(status.success(), "build through the shim succeeded");
+
+    let target_dir = fixture.workspace().join("target");
+    assert!(target_dir.is_symlink(), "target is managed by targo");
+    assert_eq!(
+        fs::read_to_string(target_dir.join("output")).unwrap(),
+        "built\n",
+        "the real cargo was run"
+    );
+}
+
+#[test]
+fn test_shim_recursion_guard() {
+    let fixture = Fixture::new("", "exit 0");
+    let shim_dir = fixture.store_dir().join("shim-bin");
+    let copy_dir = fixture.store_dir().join("copy-bin");
+    fs::create_dir_all(&shim_dir).unwrap();
+    fs::create_dir_all(&copy_dir).unwrap();
+    symlink(env!("CARGO_BIN_EXE_targo"), shim_dir.join("cargo")).unwrap();
+    // A copy of targo isn't recognized as the shim itself, so it's chosen as the real cargo.
+    fs::copy(env!("CARGO_BIN_EXE_targo"), copy_dir.join("cargo")).unwrap();
+
+    let output = fixture
+        .command(shim_dir.join("cargo"))
+        .arg("build")
+        .env_remove("CARGO")
+        .env("PATH", path_with(&[&shim_dir, &copy_dir]))
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "the shim refuses to recurse");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("it's targo itself"),
+        "error explains the recursion: {stderr}"
+    );
+}
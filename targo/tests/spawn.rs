@@ -0,0 +1,191 @@
+//! Tests for spawn mode, using a fake `CARGO` binary.
+
+mod common;
+
+use common::Fixture;
+use std::{
+    fs,
+    os::unix::{fs::PermissionsExt, process::ExitStatusExt},
+    process::Child,
+};
+
+const SPAWN_CONFIG: &str = "run-mode = \"spawn\"\n";
+
+fn send_signal(child: &Child, signal: libc::c_int) {
+    let ret = unsafe { libc::kill(child.id() as libc::pid_t, signal) };
+    assert_eq!(ret, 0, "kill succeeded");
+}
+
+#[test]
+fn test_exit_code_propagated() {
+    let fixture = Fixture::new(SPAWN_CONFIG, "exit 42");
+    let status = fixture.run_targo();
+    assert_eq!(status.code(), Some(42), "exit code propagated");
+    assert!(!fixture.is_in_use(), "lock released");
+}
+
+#[test]
+fn test_signal_exit_propagated() {
+    let fixture = Fixture::new(SPAWN_CONFIG, "kill -TERM $$");
+    let status = fixture.run_targo();
+    assert_eq!(status.signal(), Some(libc::SIGTERM), "signal propagated");
+    assert!(!fixture.is_in_use(), "lock released");
+}
+
+#[test]
+fn test_sigterm_forwarded() {
+    let fixture = Fixture::new(
+        SPAWN_CONFIG,
+        "trap 'exit 7' TERM\n\
+         touch \"$READY_FILE\"\n\
+         while :; do sleep 0.05; done",
+    );
+    let mut child = fixture.spawn_targo();
+    fixture.wait_until_ready();
+    assert!(fixture.is_in_use(), "lock held while cargo is running");
+
+    send_signal(&child, libc::SIGTERM);
+    let status = child.wait().unwrap();
+    assert_eq!(
+        status.code(),
+        Some(7),
+        "cargo handled the forwarded SIGTERM"
+    );
+    assert!(!fixture.is_in_use(), "lock released");
+}
+
+#[test]
+fn test_interrupted_build() {
+    let fixture = Fixture::new(SPAWN_CONFIG, "touch \"$READY_FILE\"\nexec sleep 30");
+    let mut child = fixture.spawn_targo();
+    fixture.wait_until_ready();
+
+    send_signal(&child, libc::SIGINT);
+    let status = child.wait().unwrap();
+    assert_eq!(
+        status.signal(),
+        Some(libc::SIGINT),
+        "targo exits via SIGINT like cargo"
+    );
+    assert!(!fixture.is_in_use(), "lock released");
+}
+
+#[test]
+fn test_stop_and_continue() {
+    let fixture = Fixture::new(
+        SPAWN_CONFIG,
+        "touch \"$READY_FILE\"\n\
+         while [ ! -e \"$READY_FILE.done\" ]; do sleep 0.05; done\n\
+         exit 3",
+    );
+    let mut child = fixture.spawn_targo();
+    fixture.wait_until_ready();
+
+    send_signal(&child, libc::SIGTSTP);
+    send_signal(&child, libc::SIGCONT);
+    fs::write(fixture.ready_file().with_extension("done"), "").unwrap();
+    let status = child.wait().unwrap();
+    assert_eq!(
+        status.code(),
+        Some(3),
+        "build completes after being resumed"
+    );
+}
+
+#[test]
+fn test_history_recorded() {
+    let fixture = Fixture::new(SPAWN_CONFIG, "echo built > target/output; exit 42");
+    fixture.run_targo();
+    fixture.run_targo();
+
+    let output = fixture.targo_command().arg("history").output().unwrap();
+    assert!(output.status.success(), "targo history succeeded");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "two builds recorded: {stdout}");
+    for line in lines {
+        assert!(line.contains("exit 42"), "exit status recorded: {line}");
+        assert!(line.contains("cargo build"), "args recorded: {line}");
+    }
+
+    let output = fixture
+        .targo_command()
+        .args(["history", "-n", "1"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 1, "limit respected: {stdout}");
+
+    let output = fixture
+        .targo_command()
+        .args(["history", "--message-format", "json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["schema-version"], 1, "schema version present");
+    let records = json["records"].as_array().unwrap();
+    assert_eq!(records.len(), 2, "two records in JSON output");
+    assert_eq!(records[0]["exit-code"], 42, "exit code in JSON output");
+    assert_eq!(
+        records[0]["subcommand"], "build",
+        "subcommand in JSON output"
+    );
+}
+
+#[test]
+fn test_history_toolchain_cached() {
+    let fixture = Fixture::new(SPAWN_CONFIG, "exit 0");
+    let rustc = fixture.path("fake-rustc");
+    let count_file = fixture.path("rustc-count");
+    fs::write(
+        &rustc,
+        format!(
+            "#!/bin/sh\necho run >> \"{}\"\necho 'rustc 1.0.0-fake'\n",
+            count_file.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&rustc, fs::Permissions::from_mode(0o755)).unwrap();
+
+    for _ in 0..2 {
+        let status = fixture
+            .targo_command()
+            .args(["wrap-cargo", "build"])
+            .env("RUSTC", &rustc)
+            .status()
+            .unwrap();
+        assert!(status.success(), "build succeeded");
+    }
+    assert_eq!(
+        fs::read_to_string(&count_file).unwrap().lines().count(),
+        1,
+        "rustc -V was only run once"
+    );
+
+    let output = fixture
+        .targo_command()
+        .args(["history", "--message-format", "json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    for record in json["records"].as_array().unwrap() {
+        assert_eq!(
+            record["toolchain"], "rustc 1.0.0-fake",
+            "toolchain recorded"
+        );
+    }
+}
+
+#[test]
+fn test_history_exec_mode() {
+    let fixture = Fixture::new("", "exit 0");
+    assert!(fixture.run_targo().success(), "build succeeded");
+
+    let output = fixture.targo_command().arg("history").output().unwrap();
+    assert!(output.status.success(), "targo history succeeded");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("history is only recorded with `run-mode = \"spawn\"`"),
+        "explains why there's no history: {stderr}"
+    );
+}
@@ -0,0 +1,125 @@
+//! Tests for `targo status`.
+
+mod common;
+
+use common::Fixture;
+use std::fs;
+
+fn status_json(fixture: &Fixture) -> serde_json::Value {
+    let output = fixture
+        .targo_command()
+        .args(["status", "--message-format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "status succeeded");
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn test_status_does_not_exist() {
+    let fixture = Fixture::new("", "exit 0");
+    let json = status_json(&fixture);
+    assert_eq!(
+        json["workspace"],
+        fixture
+            .workspace()
+            .canonicalize()
+            .unwrap()
+            .to_str()
+            .unwrap()
+    );
+    assert_eq!(json["kind"], "does-not-exist");
+    assert_eq!(json["action"], "create-symlink");
+    assert_eq!(json["managed"], serde_json::Value::Null);
+    assert!(
+        !fixture.workspace().join("target").exists(),
+        "status doesn't change anything"
+    );
+}
+
+#[test]
+fn test_status_plain_directory() {
+    let fixture = Fixture::new("", "exit 0");
+    fs::create_dir(fixture.workspace().join("target")).unwrap();
+    let json = status_json(&fixture);
+    assert_eq!(json["kind"], "directory");
+    assert_eq!(json["action"], "replace-directory");
+    assert!(
+        !fixture.workspace().join("target").is_symlink(),
+        "status doesn't change anything"
+    );
+}
+
+#[test]
+fn test_status_targo_symlink() {
+    let fixture = Fixture::new("", "echo built > target/output");
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let json = status_json(&fixture);
+    assert_eq!(json["kind"], "targo-symlink");
+    assert_eq!(json["action"], "use-symlink");
+    let managed_dir = fs::read_link(fixture.workspace().join("target")).unwrap();
+    assert_eq!(
+        json["managed"]["path"],
+        managed_dir.parent().unwrap().to_str().unwrap()
+    );
+    assert_eq!(json["managed"]["root"], "default");
+    assert_eq!(json["managed"]["size"], 6, "size of the build output");
+}
+
+#[test]
+fn test_status_foreign_symlink() {
+    let fixture = Fixture::new("", "exit 0");
+    let elsewhere = fixture.path("elsewhere");
+    fs::create_dir(&elsewhere).unwrap();
+    std::os::unix::fs::symlink(&elsewhere, fixture.workspace().join("target")).unwrap();
+    let json = status_json(&fixture);
+    assert_eq!(json["kind"], "foreign");
+    assert_eq!(json["action"], "leave-alone");
+}
+
+#[test]
+fn test_status_excluded() {
+    let fixture = Fixture::new("", "exit 0");
+    fixture.write_config(&format!(
+        "[[workspace]]\npath = \"{}\"\nexclude = true\n",
+        fixture.workspace().canonicalize().unwrap().display()
+    ));
+    let json = status_json(&fixture);
+    assert_eq!(json["excluded"], true);
+    assert_eq!(json["action"], "excluded");
+
+    // Human-readable output too.
+    let output = fixture.targo_command().arg("status").output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("none (workspace is excluded by config)"),
+        "action is described: {stdout}"
+    );
+}
+
+#[test]
+fn test_status_not_writable() {
+    let fixture = Fixture::new("", "exit 0");
+    if !fixture.make_workspace_read_only() {
+        eprintln!("skipping test: the workspace is still writable (running as root?)");
+        return;
+    }
+    let json = status_json(&fixture);
+    assert_eq!(json["kind"], "does-not-exist");
+    assert_eq!(json["action"], "not-writable");
+}
+
+#[test]
+fn test_status_not_in_workspace() {
+    let fixture = Fixture::new("", "exit 1");
+    fs::remove_file(fixture.workspace().join("Cargo.toml")).unwrap();
+    // The fake Cargo always finds a workspace, so make locate-project fail too.
+    fs::write(
+        fixture.fake_cargo(),
+        "#!/bin/sh\necho 'error: could not find `Cargo.toml`' >&2\nexit 101\n",
+    )
+    .unwrap();
+    let json = status_json(&fixture);
+    assert_eq!(json["workspace"], serde_json::Value::Null);
+    assert_eq!(json["action"], "not-in-workspace");
+}
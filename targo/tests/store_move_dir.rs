@@ -0,0 +1,97 @@
+//! Tests for `targo store move-dir`.
+
+mod common;
+
+use common::Fixture;
+use std::fs;
+
+#[test]
+fn test_move_dir() {
+    let fixture = Fixture::new("", "echo built > target/output");
+    let other_root = fixture.path("other-root");
+    fixture.write_config(&format!(
+        "[[root]]\nname = \"other\"\npath = \"{}\"\n",
+        other_root.display()
+    ));
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let target_dir = fixture.workspace().join("target");
+    let old_dest = fs::read_link(&target_dir).unwrap();
+    assert!(
+        old_dest.starts_with(fixture.store_dir()),
+        "new directories are in the default root"
+    );
+
+    let output = fixture
+        .targo_command()
+        .args([
+            "store",
+            "move-dir",
+            ".",
+            "other",
+            "--message-format",
+            "json",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "move-dir succeeded");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["from-root"], "default");
+    assert_eq!(json["to-root"], "other");
+
+    let new_dest = fs::read_link(&target_dir).unwrap();
+    assert!(
+        new_dest.starts_with(&other_root),
+        "the symlink was re-pointed to the new root: {}",
+        new_dest.display()
+    );
+    assert_eq!(
+        new_dest.file_name(),
+        old_dest.file_name(),
+        "the directory keeps its name"
+    );
+    assert!(!old_dest.exists(), "the old directory is gone");
+    assert_eq!(
+        fs::read_to_string(target_dir.join("output")).unwrap(),
+        "built\n",
+        "build output was moved"
+    );
+
+    // Later builds keep using the new location.
+    assert!(fixture.run_targo().success(), "build succeeded");
+    assert_eq!(fs::read_link(&target_dir).unwrap(), new_dest);
+
+    // Moving to the root the directory is already in does nothing.
+    let status = fixture
+        .targo_command()
+        .args(["store", "move-dir", ".", "other"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "move-dir succeeded");
+    assert_eq!(fs::read_link(&target_dir).unwrap(), new_dest);
+}
+
+#[test]
+fn test_move_dir_unknown_root() {
+    let fixture = Fixture::new("", "exit 0");
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let target_dir = fixture.workspace().join("target");
+    let dest = fs::read_link(&target_dir).unwrap();
+
+    let output = fixture
+        .targo_command()
+        .args(["store", "move-dir", ".", "nope"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "move-dir failed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("unknown store root `nope`"),
+        "error names the root: {stderr}"
+    );
+    assert_eq!(
+        fs::read_link(&target_dir).unwrap(),
+        dest,
+        "nothing was moved"
+    );
+    assert!(dest.is_dir(), "the managed directory is still there");
+}
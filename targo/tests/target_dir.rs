@@ -0,0 +1,85 @@
+//! Tests for `targo target-dir`.
+
+mod common;
+
+use common::Fixture;
+use std::{fs, process::Output};
+
+fn target_dir(fixture: &Fixture, args: &[&str]) -> Output {
+    fixture
+        .targo_command()
+        .arg("target-dir")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout_line(output: &Output) -> String {
+    assert!(output.status.success(), "target-dir succeeded");
+    String::from_utf8(output.stdout.clone())
+        .unwrap()
+        .trim_end()
+        .to_owned()
+}
+
+#[test]
+fn test_target_dir() {
+    let fixture = Fixture::new("", "exit 0");
+    let output = target_dir(&fixture, &[]);
+    assert_eq!(
+        output.status.code(),
+        Some(5),
+        "unmanaged before the first build"
+    );
+
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let symlink = fixture.workspace().join("target");
+    let managed = fs::canonicalize(&symlink).unwrap();
+
+    let output = target_dir(&fixture, &[]);
+    assert_eq!(stdout_line(&output), managed.to_str().unwrap());
+    let output = target_dir(&fixture, &["--print", "symlink"]);
+    assert_eq!(stdout_line(&output), symlink.to_str().unwrap());
+    let output = target_dir(&fixture, &["--print", "encoded"]);
+    let encoded = stdout_line(&output);
+    assert_eq!(
+        managed.parent().unwrap().file_name().unwrap(),
+        encoded.as_str(),
+        "encoded name is the managed directory's name"
+    );
+    let output = target_dir(&fixture, &["--print", "root"]);
+    assert_eq!(
+        stdout_line(&output),
+        managed
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .to_str()
+            .unwrap(),
+        "root contains the managed directory"
+    );
+}
+
+#[test]
+fn test_target_dir_exit_codes() {
+    let fixture = Fixture::new("", "exit 0");
+    fs::write(
+        fixture.store_dir().join("config.toml"),
+        format!(
+            "[[workspace]]\npath = {:?}\nexclude = true\n",
+            fs::canonicalize(fixture.workspace()).unwrap()
+        ),
+    )
+    .unwrap();
+    let output = target_dir(&fixture, &[]);
+    assert_eq!(output.status.code(), Some(4), "excluded");
+
+    let output = fixture
+        .targo_command()
+        .arg("target-dir")
+        .env("CARGO", "false")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3), "not a Cargo workspace");
+}
@@ -0,0 +1,137 @@
+//! Tests for volatile storage roots: eviction, snapshots and restoring them.
+
+mod common;
+
+use common::{managed_dirs, set_last_used_days_ago, Fixture};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+fn volatile_config(root: &Path, min_free_percent: u8) -> String {
+    format!(
+        "[[root]]\n\
+         name = \"tmp\"\n\
+         path = \"{}\"\n\
+         volatile = true\n\
+         min-free-percent = {min_free_percent}\n\
+         persist-to = \"default\"\n\
+         \n\
+         [[root-rule]]\n\
+         root = \"tmp\"\n",
+        root.display()
+    )
+}
+
+fn add_workspace(fixture: &Fixture, name: &str) -> PathBuf {
+    let dir = fixture.workspace().join(name);
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("Cargo.toml"), "").unwrap();
+    dir
+}
+
+fn build_in(fixture: &Fixture, dir: &Path) {
+    let status = fixture
+        .targo_command()
+        .current_dir(dir)
+        .args(["wrap-cargo", "build"])
+        .status()
+        .unwrap();
+    assert!(status.success(), "build in `{}` succeeded", dir.display());
+}
+
+#[test]
+fn test_eviction() {
+    let fixture = Fixture::new("", "exit 0");
+    let root = fixture.path("volatile");
+    // The filesystem is always "low on space" with this setting.
+    fixture.write_config(&volatile_config(&root, 100));
+
+    let first = add_workspace(&fixture, "first");
+    let second = add_workspace(&fixture, "second");
+    let third = add_workspace(&fixture, "third");
+    build_in(&fixture, &first);
+    let dirs = managed_dirs(&root);
+    assert_eq!(dirs.len(), 1, "the directory is in the volatile root");
+    let first_dir = dirs[0].clone();
+
+    build_in(&fixture, &second);
+    assert!(
+        first_dir.exists(),
+        "recently used directories aren't evicted"
+    );
+
+    set_last_used_days_ago(&first_dir, 1);
+    build_in(&fixture, &third);
+    assert!(!first_dir.exists(), "the idle directory was evicted");
+    assert_eq!(
+        managed_dirs(&root).len(),
+        2,
+        "the recently used directories were kept"
+    );
+
+    // The evicted directory is recreated on the next build.
+    build_in(&fixture, &first);
+    assert!(first_dir.is_dir(), "the directory was recreated");
+    assert!(first.join("target").is_dir(), "the symlink works again");
+}
+
+#[test]
+fn test_sync_and_restore() {
+    // Report the previous build's output, then write a new one.
+    let fixture = Fixture::new(
+        "",
+        "if [ -f target/output ]; then cp target/output \"$READY_FILE\"; fi\n\
+         echo built > target/output",
+    );
+    let root = fixture.path("volatile");
+    fixture.write_config(&volatile_config(&root, 0));
+
+    assert!(fixture.run_targo().success(), "build succeeded");
+    let dirs = managed_dirs(&root);
+    assert_eq!(dirs.len(), 1, "the directory is in the volatile root");
+    let encoded = dirs[0].file_name().unwrap();
+
+    let output = fixture
+        .targo_command()
+        .args(["store", "sync", ".", "--message-format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "sync succeeded");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let snapshot = fixture.store_dir().join(".targo-snapshots").join(encoded);
+    assert_eq!(json["snapshot"], snapshot.to_str().unwrap());
+    assert_eq!(
+        fs::read_to_string(snapshot.join("target/output")).unwrap(),
+        "built\n",
+        "the snapshot has the build output"
+    );
+
+    // Simulate a reboot wiping the volatile root.
+    fs::remove_dir_all(&root).unwrap();
+    assert!(fixture.run_targo().success(), "build succeeded");
+    assert_eq!(
+        fs::read_to_string(fixture.ready_file()).unwrap(),
+        "built\n",
+        "the build saw the restored output"
+    );
+    assert!(dirs[0].is_dir(), "the directory was restored");
+}
+
+#[test]
+fn test_sync_requires_persist_to() {
+    let fixture = Fixture::new("", "exit 0");
+    assert!(fixture.run_targo().success(), "build succeeded");
+
+    let output = fixture
+        .targo_command()
+        .args(["store", "sync", "."])
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "sync failed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("isn't a volatile root with persist-to configured"),
+        "error explains the problem: {stderr}"
+    );
+}